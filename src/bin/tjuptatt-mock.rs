@@ -0,0 +1,57 @@
+//! 模拟 tjupt 的本地服务器，用于手动测试
+//!
+//! 在配置文件的 `[global]` 中设置 `site_url` 及 `suggest_url` 指向此服务器
+
+use clap::{crate_version, Arg, ArgAction, Command};
+use libs::{
+    error::Result,
+    mock::{MockServer, Scenario, MOCK_PWD, MOCK_USER},
+};
+use std::net::SocketAddr;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mat = Command::new("tjuptatt-mock")
+        .version(crate_version!())
+        .about("模拟 tjupt 的本地服务器")
+        .arg(
+            Arg::new("addr")
+                .long("addr")
+                .short('a')
+                .help("监听的地址")
+                .action(ArgAction::Set)
+                .default_value("127.0.0.1:8080")
+                .value_parser(clap::value_parser!(SocketAddr))
+                .value_name("ADDR"),
+        )
+        .arg(
+            Arg::new("scenario")
+                .long("scenario")
+                .short('s')
+                .help("模拟的情况")
+                .action(ArgAction::Set)
+                .default_value("success")
+                .value_parser([
+                    "success",
+                    "attended",
+                    "wrong-password",
+                    "makeup",
+                    "layout-change",
+                ])
+                .value_name("SCENARIO"),
+        )
+        .get_matches();
+
+    let addr = *mat.get_one::<SocketAddr>("addr").unwrap();
+    let scenario: Scenario = mat.get_one::<String>("scenario").unwrap().parse()?;
+    let server = MockServer::bind(addr, scenario).await?;
+
+    println!("模拟服务器: {}, 情况: {}", server.url(), scenario);
+    println!("用户名: {}, 密码: {}", MOCK_USER, MOCK_PWD);
+    println!("配置文件 [global] 中设置:");
+    println!("site_url = \"{}\"", server.url());
+    println!("suggest_url = \"{}\"", server.suggest_url());
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
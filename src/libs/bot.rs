@@ -1,38 +1,47 @@
 //! 主要逻辑
 
-use crate::config::EmailConfig;
+use crate::report::{
+    error_status, is_network_error, Attended, OptionScore, RunStatus, RunSummary, UserOutcome,
+};
+use crate::{
+    command::{SiteUrls, DIRS},
+    config::{ConfigFile, HttpConfig, ProxyConfig, UserConfig},
+};
 use crate::{
-    command::{tjurls, DIRS},
-    config::{ConfigFile, UserConfig},
+    cookie::{CookieStorage, FileStorage},
+    dataset::{self, Dataset},
+    debug_dump::{DebugDump, Trace},
+    doctor,
+    error::{Context, Error, ErrorKind, Result},
+    http::client_builder,
+    live::LiveView,
+    logger,
+    notify::{Channel, Notice, Notifier},
+    picparser::{self, Fit, PosterClient, RateLimiter},
+    progress::{Event, Progress},
+    similarity::{self, MetricKind, SimilarityMetric},
+    solve,
+    tape::{self, Tape},
+    termimg,
+    transport::{self, Transport},
 };
-use crate::{email_bot, picparser};
-use anyhow::{anyhow, Result};
+use clap::ArgMatches;
 use lazy_static::lazy_static;
-use reqwest::{header::HeaderMap, redirect, Client, ClientBuilder};
-use reqwest_cookie_store::{CookieStore, CookieStoreMutex};
+use rand::Rng;
+use reqwest::{Client, Request, Response};
+use reqwest_cookie_store::CookieStoreMutex;
 use scraper::{Html, Selector};
 use std::{
+    collections::BTreeMap,
     fs::{remove_dir_all, File},
-    io::{BufReader, Write},
+    io::{IsTerminal, Write},
     path::{Path, PathBuf},
-    sync::Arc,
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
+use tokio::sync::Semaphore;
 
 lazy_static! {
-    static ref HEADER: HeaderMap = {
-        let mut head = HeaderMap::new();
-        head.insert(
-            "User-Agent",
-            "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
-            AppleWebKit/537.36 (KHTML, like Gecko) \
-            Chrome/100.0.0.0 Safari/537.36"
-                .parse()
-                .unwrap(),
-        );
-        head
-    };
-
     // //input[@type="radio"]
     // //input[@type="submit"]
     static ref INPUT_RADIO_SELE: Selector = Selector::parse(r#"input[type="radio"]"#).unwrap();
@@ -47,199 +56,304 @@ lazy_static! {
 #[derive(Debug)]
 pub struct TjuPtUser {
     config: UserConfig,
-    client: Client,
+    /// 访问 tjupt 使用的，默认是带 `cookie` 的 `reqwest::Client`
+    transport: Arc<dyn Transport>,
+    /// 默认的 `reqwest::Client`，自定义 transport 时为 `None`
+    client: Option<Client>,
+    /// tjupt 站点的链接
+    urls: SiteUrls,
+    /// 访问海报来源(豆瓣)使用的，与 `transport` 的代理及cookie分开
+    poster_client: PosterClient,
     cookie: Arc<CookieStoreMutex>,
-    cookie_path: Option<PathBuf>,
+    /// 保存 cookie 的位置，不设置则不保存
+    cookie_storage: Option<Arc<dyn CookieStorage>>,
+    /// 签到失败时的提醒
+    notifier: Arc<Notifier>,
+    /// 签到过程中的事件，不设置则不发出
+    progress: Option<Progress>,
+    /// 保存签到数据，不设置则不保存
+    dataset: Option<Dataset>,
+    /// 签到失败时保存调试数据，不设置则不保存
+    debug_dump: Option<DebugDump>,
+    /// 当前这次签到收集的调试数据
+    trace: Mutex<Trace>,
+    /// 记录或回放请求，与 `poster_client` 共用
+    tape: Option<Arc<Tape>>,
 }
 
 impl TjuPtUser {
-    pub fn from_config<P>(userconfig: UserConfig, status_dir: Option<P>) -> Self
-    where
-        P: AsRef<Path>,
-    {
-        let cookie_path =
-            status_dir.map(|p| p.as_ref().join(format!("{}_cookie.json", userconfig.id())));
-
-        Self::new(userconfig, cookie_path)
+    /// 不使用配置文件创建
+    pub fn builder(id: &str, pwd: &str) -> TjuPtUserBuilder {
+        TjuPtUserBuilder::new(id, pwd)
     }
 
-    fn new<P>(config: UserConfig, cookie_path: Option<P>) -> Self
+    /// `status_dir` 为保存 cookie 的文件夹，不指定则不保存
+    pub fn from_config<P>(
+        userconfig: UserConfig,
+        http: &HttpConfig,
+        status_dir: Option<P>,
+    ) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let cookie = Arc::new(CookieStoreMutex::default());
-
-        let client = ClientBuilder::new()
-            .timeout(Duration::from_secs(5))
-            .cookie_store(true)
-            .cookie_provider(cookie.clone())
-            .connection_verbose(false)
-            .default_headers(HEADER.clone())
-            .redirect(redirect::Policy::limited(3))
-            .build()
-            .unwrap();
-
-        let cookie_path = match cookie_path {
-            None => None,
-            Some(p) => {
-                let p = p.as_ref();
-                Some(p.into())
-            }
-        };
-
-        Self {
-            client,
-            config,
-            cookie,
-            cookie_path,
+        let mut builder = TjuPtUserBuilder::from_config(userconfig).http(http.clone());
+        if let Some(dir) = status_dir {
+            let id = builder.config.id().to_owned();
+            builder = builder.cookie_file(dir.as_ref().join(format!("{}_cookie.json", id)));
         }
+        builder.build()
     }
 
     /// 加载cookie
     ///
     /// 如果未设置cookiepath也返回Ok
     fn load_cookie(&self) -> Result<()> {
-        if let Some(ref cookie_path) = self.cookie_path {
-            let cookie_path = cookie_path.as_path();
-            if cookie_path.is_file() {
-                let file = File::open(cookie_path).map(BufReader::new)?;
-                let cookie = CookieStore::load_json(file).map_err(|e| anyhow!("{}", e))?;
-                let mut lock = self.cookie.lock().map_err(|e| anyhow!("{}", e))?;
-                *lock = cookie;
-
-                // log::debug!("加载cookie成功 {}", cookie_path.display());
-            } else {
+        if let Some(ref storage) = self.cookie_storage {
+            match storage.load()? {
+                Some(cookie) => {
+                    let mut lock = self.cookie.lock().expect("cookie 的锁已损坏");
+                    *lock = cookie;
+                }
                 // log::debug!("本地cookie不存在 {}", cookie_path.display());
-                return Err(anyhow!("coookie 不存在"));
+                None => return Err(Error::io("coookie 不存在")),
             }
         }
         Ok(())
     }
 
+    /// 检查本地保存的cookie是否有效，不会登陆
+    ///
+    /// 没有保存cookie时返回错误
+    pub async fn check_cookie(&self) -> Result<bool> {
+        self.load_cookie()?;
+        let req = self.send(transport::get(&self.urls.attendance())?).await?;
+        Ok(!req.url().as_str().contains("login.php"))
+    }
+
     /// 在不加载cookie的情况下登陆
     ///
     /// 返回签到页面的String
     async fn login(&self) -> Result<String> {
-        let _r = self.client.get(tjurls::LOGIN).send().await?;
-        if !self
-            .client
-            .post(tjurls::TAKELOGIN)
-            // .query(&[("returnto", "attendance.php")])
-            .form(&[
+        let r = self.send(transport::get(&self.urls.login())?).await?;
+        self.read_text("login", r).await?;
+        let req = transport::post_form(
+            &self.urls.takelogin(),
+            &[
                 ("username", self.config.id()),
                 ("password", self.config.pwd()),
                 ("logout", "7days"),
                 ("returnto", "attendance.php"),
-            ])
-            .send()
-            .await?
-            .status()
-            .is_success()
-        {
-            return Err(anyhow!("请检查网络"));
-        }
-        let req = self.client.get(tjurls::ATTENDANCE).send().await?;
-        if !req.url().as_str().contains("login.php") {
-            let content = req.text().await?;
+            ],
+        )?;
+        let r = self.send(req).await?;
+        let success = r.status().is_success();
+        self.read_text("takelogin", r).await?;
+        if !success {
+            return Err(Error::network("请检查网络"));
+        }
+        let req = self.send(transport::get(&self.urls.attendance())?).await?;
+        let need_login = req.url().as_str().contains("login.php");
+        let content = self.read_text("attendance", req).await?;
+        if !need_login {
             Ok(content)
         } else {
-            Err(anyhow!("发送登陆请求失败"))
+            Err(Error::auth("发送登陆请求失败"))
         }
     }
 
+    /// 发送请求，设置了记录或回放时经过 `tape`
+    async fn send(&self, req: Request) -> Result<Response> {
+        tape::send(self.tape.as_deref(), &*self.transport, req).await
+    }
+
+    /// 读取响应，开启调试数据时记录在 `trace` 中
+    async fn read_text(&self, step: &str, r: Response) -> Result<String> {
+        let url = r.url().to_string();
+        let status = r.status().as_u16();
+        let text = r.text().await?;
+        if self.debug_dump.is_some() {
+            if let Ok(mut trace) = self.trace.lock() {
+                trace.response(step, &url, status, &text);
+            }
+        }
+        Ok(text)
+    }
+
     /// 登陆
     /// 在这之前加载过cookie了
     async fn get_att_html(&self) -> Result<String> {
-        let req = self.client.get(tjurls::ATTENDANCE).send().await?;
+        let req = self.send(transport::get(&self.urls.attendance())?).await?;
         // 先获取签到页面，检查链接
         if req.url().as_str().contains("login.php") {
             // 如果重定向了说明需要登陆
             self.login().await
         } else {
             // 如果成功，那么就直接ok
-            let content = req.text().await?;
-            Ok(content)
+            self.read_text("attendance", req).await
+        }
+    }
+
+    /// 发出事件，没有设置 `progress` 则忽略
+    fn emit(&self, event: Event) {
+        if let Some(ref progress) = self.progress {
+            progress.emit(self.config.id(), event);
         }
     }
 
+    /// 获取签到页面，解析出题图及选项
+    async fn get_kaptcha(&self) -> Result<(picparser::Kaptcha, Vec<picparser::Answer>)> {
+        self.emit(Event::LoginStarted);
+        let html = self
+            .get_att_html()
+            .await
+            .context(format!("{} 登录失败", self.config.id()))?;
+        self.emit(Event::LoginSucceeded);
+        let (mut kaptcha, answers) = parse_att_html(&html, &self.urls)?;
+        self.emit(Event::PageParsed {
+            options: answers.len(),
+        });
+        if let Some(ref progress) = self.progress {
+            let progress = progress.clone();
+            let user = self.config.id().to_owned();
+            kaptcha.set_on_scored(move |a| {
+                progress.emit(
+                    &user,
+                    Event::OptionScored {
+                        name: a.name.clone(),
+                        score: a.score,
+                        error: a.error.clone(),
+                    },
+                )
+            });
+        }
+        let img = self
+            .send(transport::get(&kaptcha.url)?)
+            .await?
+            .bytes()
+            .await?;
+        kaptcha.set_img(img)?;
+        kaptcha.set_metric(self.config.metric());
+        kaptcha.set_fit(self.config.fit());
+        Ok((kaptcha, answers))
+    }
+
     /// 签到一次
     ///
     /// 但是不在这里加载cookie
     /// 也不在这登录
-    async fn att_onece_now(&self) -> Result<()> {
-        let Ok(html) = self.get_att_html().await else {
-            return Err(anyhow!("{} 登录失败", self.config.id()));
-        };
-
-        // 解析网页，获取选项信息
-        let doc = Html::parse_document(&html);
-
-        // //input[@type="radio"]s
-        let radio = doc.select(&INPUT_RADIO_SELE);
-
-        // 选项们
-        let answers = radio
-            .into_iter()
-            .filter_map(|e| {
-                let Some(name) = e.next_sibling()  else {
-                return None;
-            };
-                let Some(name) = name.value().as_text() else {
-                return None;
-            };
-                // let name = name.to_string();
+    ///
+    /// 返回提交的答案，失败时保存调试数据
+    async fn att_onece_now(&self) -> Result<String> {
+        if let Ok(mut trace) = self.trace.lock() {
+            *trace = Trace::default();
+        }
+        let res = self.att_onece().await;
+        if let Err(ref e) = res {
+            self.dump(e);
+        }
+        res
+    }
 
-                let Some(value) = e.value().attr("value") else {
-                return None;
-            };
+    /// 保存调试数据，失败只记录日志
+    fn dump(&self, err: &Error) {
+        let Some(ref debug_dump) = self.debug_dump else {
+            return;
+        };
+        let Ok(trace) = self.trace.lock() else {
+            return;
+        };
+        match debug_dump.save(self.config.id(), &[self.config.pwd()], &trace, err) {
+            Ok(dir) => log::info!("保存调试数据: {}", dir.display()),
+            Err(e) => log::warn!("无法保存调试数据: {}", e.report()),
+        }
+    }
 
-                Some((name.to_string(), value.to_string()))
-            })
-            .collect::<Vec<_>>();
+    async fn att_onece(&self) -> Result<String> {
+        let (mut kaptcha, mut answers) = self.get_kaptcha().await?;
 
-        // 图片
-        let Some(img) = doc.select(&TD).next().
-            and_then(|e|e.select(&IMG).next())
-            .and_then(|e| e.value().attr("src") ) 
-        else {
-            return Err(anyhow!("无法定位图片"));
+        // 获取结果
+        // 保存数据时计算所有选项，保证每个选项都有海报
+        let result = if self.dataset.is_some() {
+            kaptcha
+                .score_answers(&mut answers, &*self.transport, &self.poster_client)
+                .await
+                .and_then(|()| {
+                    picparser::pick_answer(&answers, self.config.threshold())
+                        .cloned()
+                        .ok_or_else(|| Error::captcha("所有比较均失败了"))
+                })
+        } else {
+            kaptcha
+                .compare_with_answers(
+                    &mut answers,
+                    &*self.transport,
+                    &self.poster_client,
+                    self.config.threshold(),
+                )
+                .await
+        };
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                self.record(&kaptcha, &answers, None, &Ok(()));
+                return Err(e);
+            }
         };
 
-        let img_url = format!("https://tjupt.org{}", img);
-
-        // log::debug!("获取的图片链接: {}", img_url);
-        // 这里检查一下图片应该是jpg结尾的
-        // if !img_url.ends_with(".jpg") {
-        //     return Err(anyhow!("无法获取jpg格式图片"));
-        // }
+        log::info!("结果是: {}", result.name);
 
-        // for (x, y) in answers.iter() {
-        //     log::debug!("选项: {}, {}", x, y);
-        // }
+        let name = result.name.clone();
+        let posted = self.post_answer(&result.value).await;
+        self.record(&kaptcha, &answers, Some(&result.value), &posted);
+        self.emit(Event::AnswerSubmitted {
+            answer: name.clone(),
+            score: result.score,
+            accepted: posted.is_ok(),
+        });
+        posted?;
+        Ok(name)
+    }
 
-        if answers.is_empty() {
-            // 如果是空的，说明签到完了，或者需要补签
-            return Err(anyhow!("无法找到选项，可能已经签到，或需要补签"));
+    /// 保存签到数据，失败只记录日志，开启调试数据时同时记录在 `trace` 中
+    ///
+    /// `answer` 为提交的答案的值，`posted` 为提交的结果，网络错误时不能确定答案是否正确
+    fn record(
+        &self,
+        kaptcha: &picparser::Kaptcha,
+        answers: &[picparser::Answer],
+        answer: Option<&str>,
+        posted: &Result<()>,
+    ) {
+        if self.debug_dump.is_some() {
+            if let Ok(mut trace) = self.trace.lock() {
+                trace.kaptcha(kaptcha, answers, answer);
+            }
         }
+        let Some(ref dataset) = self.dataset else {
+            return;
+        };
+        let verified = match (answer, posted) {
+            (None, _) => None,
+            (Some(_), Ok(())) => Some(true),
+            (Some(_), Err(e)) if is_network_error(e) => None,
+            (Some(_), Err(_)) => Some(false),
+        };
+        match dataset.save(self.config.id(), kaptcha, answers, answer, verified) {
+            Ok(dir) => log::debug!("保存签到数据: {}", dir.display()),
+            Err(e) => log::warn!("无法保存签到数据: {}", e.report()),
+        }
+    }
 
-        // 获取结果
-        let mut answers: Vec<_> = answers.into_iter().map(picparser::Answer::from).collect();
-        let mut kaptcha = picparser::Kaptcha::new(img_url);
-
-        let result = tokio::task::block_in_place(move || {
-            tokio::runtime::Handle::current().block_on(async move {
-                kaptcha
-                    .compare_with_answers(&mut answers, &self.client, 93.0)
-                    .await
-            })
-        })?;
-
-        log::info!("结果是: {}", result.name);
-
-        tokio::task::block_in_place(move || {
-            tokio::runtime::Handle::current()
-                .block_on(async move { self.post_answer(&result.value).await })
-        })
+    /// 只计算答案，不提交
+    ///
+    /// 尝试加载cookie一次，返回计算过相似度的所有选项
+    pub async fn dry_run(&self) -> Result<Vec<picparser::Answer>> {
+        let _res = self.load_cookie();
+        let (mut kaptcha, mut answers) = self.get_kaptcha().await?;
+        kaptcha
+            .score_answers(&mut answers, &*self.transport, &self.poster_client)
+            .await?;
+        Ok(answers)
     }
 
     /// 签到
@@ -249,92 +363,249 @@ impl TjuPtUser {
     /// 尝试加载cookie一次
     ///
     /// 并立即签到
-    pub async fn att_now(&self, enable_email: bool, email_config: Arc<EmailConfig>) -> Result<()> {
+    ///
+    /// 失败时返回最后一次的错误
+    ///
+    /// 失败时通过设置的 `notifier` 发送提醒
+    ///
+    /// 设置了 `progress` 时发出每个阶段的事件
+    pub async fn att_now(&self) -> Result<Attended> {
         // 这里加载一次cookie就好
         let _res = self.load_cookie();
 
         let retry_times = self.config.retry();
+        // 只有重试次数为 0 时才会用到
+        let mut last_err = Error::config("重试次数为 0，没有进行签到");
         for i in 0..retry_times {
             // // 为了豆瓣正常访问需要等待
             // if i > 0 && i < retry_times-1 {
             //     tokio::time::sleep(Duration::from_secs(10)).await;
             // }
 
-            if let Err(e) = self.att_onece_now().await {
-                log::debug!(
-                    "{} 签到失败 {}/{} Error: {}",
-                    self.config.id(),
-                    i + 1,
-                    retry_times,
-                    e
-                );
-                continue;
-            } else {
-                log::info!("签到成功: {}", self.config.id());
-                return Ok(());
+            self.emit(Event::AttemptStarted {
+                attempt: i + 1,
+                retry: retry_times,
+            });
+            match self.att_onece_now().await {
+                Err(e) => {
+                    log::debug!(
+                        "{} 签到失败 {}/{} Error: {}",
+                        self.config.id(),
+                        i + 1,
+                        retry_times,
+                        e.report()
+                    );
+                    if i + 1 < retry_times {
+                        self.emit(Event::RetryScheduled {
+                            attempt: i + 1,
+                            error: e.report().to_string(),
+                        });
+                    }
+                    last_err = e;
+                    continue;
+                }
+                Ok(answer) => {
+                    log::info!("签到成功: {}", self.config.id());
+                    self.emit(Event::Finished {
+                        answer: Some(answer.clone()),
+                        attempts: i + 1,
+                        error: None,
+                    });
+                    return Ok(Attended {
+                        answer,
+                        attempts: i + 1,
+                    });
+                }
             }
         }
 
-        if enable_email {
-            if let Some(rec) = self.config.email() {
-                if let Err(e) = email_bot::send_email(
-                    &email_config,
-                    rec,
-                    format!("{} 签到失败", self.config.id()).as_str(),
-                ) {
-                    log::error!("邮件发送失败!, Err: {}", e);
-                }
+        self.notifier
+            .notify(&Notice {
+                user: self.config.id(),
+                to: self.config.email(),
+                content: format!("{} 签到失败", self.config.id()).as_str(),
+            })
+            .await;
+
+        let err = last_err.context(format!("签到失败: {}", self.config.id()));
+        self.emit(Event::Finished {
+            answer: None,
+            attempts: retry_times,
+            error: Some(err.report().to_string()),
+        });
+        Err(err)
+    }
+
+    /// 手动签到
+    ///
+    /// 尝试加载cookie一次
+    ///
+    /// 在终端用 `protocol` 显示题图，列出每个选项及其相似度，由用户选择后提交
+    pub async fn att_interactive(&self, protocol: termimg::Protocol) -> Result<Attended> {
+        let _res = self.load_cookie();
+        let (mut kaptcha, mut answers) = self.get_kaptcha().await?;
+        kaptcha
+            .score_answers(&mut answers, &*self.transport, &self.poster_client)
+            .await?;
+
+        println!("{}:", self.config.id());
+        if let Some(ref img) = kaptcha.img_bytes {
+            match termimg::render(img, protocol, 30) {
+                Ok(img) => print!("{}", img),
+                Err(e) => log::warn!("无法显示题图: {}", e.report()),
             }
         }
 
-        Err(anyhow!("签到失败: {}", self.config.id()))
+        let picked =
+            picparser::pick_answer(&answers, self.config.threshold()).map(|a| a.value.clone());
+        let recommended = answers
+            .iter()
+            .position(|a| Some(&a.value) == picked.as_ref());
+        for (n, a) in answers.iter().enumerate() {
+            let score = OptionScore {
+                name: a.name.clone(),
+                score: a.score,
+            };
+            let mark = if Some(n) == recommended {
+                " (推荐)"
+            } else {
+                ""
+            };
+            println!("    [{}] {}{}", n + 1, score, mark);
+        }
+
+        let len = answers.len();
+        let Some(n) = tokio::task::spawn_blocking(move || choose_answer(len, recommended))
+            .await
+            .map_err(|e| Error::io("无法读取输入").with_source(e))??
+        else {
+            return Err(Error::cancelled("已跳过"));
+        };
+        let answer = &answers[n];
+        let posted = self.post_answer(&answer.value).await;
+        self.record(&kaptcha, &answers, Some(&answer.value), &posted);
+        posted?;
+        log::info!("签到成功: {}", self.config.id());
+        Ok(Attended {
+            answer: answer.name.clone(),
+            attempts: 1,
+        })
+    }
+
+    /// 用户配置
+    pub fn config(&self) -> &UserConfig {
+        &self.config
     }
 
     /// 清除cookie
     pub fn clear_cookie(&self) -> Result<()> {
-        let Ok(mut lock) = self.cookie.lock() else {
-            return Err(anyhow!("无法获取锁"));
-        };
-        lock.clear();
+        self.cookie.lock().expect("cookie 的锁已损坏").clear();
         Ok(())
     }
 
-    pub fn client(&self) -> &Client {
-        &self.client
+    /// 访问 tjupt 使用的
+    pub fn transport(&self) -> &dyn Transport {
+        &*self.transport
+    }
+
+    /// 访问 tjupt 使用的 `reqwest::Client`，替换 transport 后返回 `None`
+    #[deprecated(note = "使用 `transport()`")]
+    pub fn client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+
+    /// 替换访问 tjupt 使用的 transport，比如带中间件的 client 或者模拟的响应
+    ///
+    /// 替换后不再加载及保存 cookie，需要 `transport` 自行处理
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.transport = Arc::new(transport);
+        self.client = None;
+        self.cookie_storage = None;
+    }
+
+    /// 访问海报来源使用的 client
+    pub fn poster_client(&self) -> &PosterClient {
+        &self.poster_client
+    }
+
+    /// 替换访问海报来源使用的 transport
+    pub fn set_poster_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.poster_client.set_transport(transport);
+    }
+
+    /// 设置签到失败时的提醒
+    pub fn set_notifier(&mut self, notifier: Arc<Notifier>) {
+        self.notifier = notifier;
+    }
+
+    /// 设置接收签到事件的 `progress`
+    pub fn set_progress(&mut self, progress: Progress) {
+        self.progress = Some(progress);
+    }
+
+    /// 设置保存签到数据的文件夹
+    pub fn set_dataset(&mut self, dataset: Dataset) {
+        self.dataset = Some(dataset);
+    }
+
+    /// 设置 tjupt 站点的地址，比如测试用的服务器
+    pub fn set_site_url(&mut self, base: &str) {
+        self.urls = SiteUrls::new(base);
+    }
+
+    /// 设置豆瓣搜索建议的链接
+    pub fn set_suggest_url(&mut self, url: &str) {
+        self.poster_client.set_suggest_url(url);
+    }
+
+    /// 记录或回放此用户的所有请求，包括访问海报来源的
+    ///
+    /// 回放时不加载及保存 cookie，也不保存到数据集，避免影响真实的签到
+    pub fn set_tape(&mut self, tape: Tape) {
+        if tape.is_replay() {
+            self.cookie_storage = None;
+            self.dataset = None;
+        }
+        let tape = Arc::new(tape);
+        self.poster_client.set_tape(tape.clone());
+        self.tape = Some(tape);
+    }
+
+    /// 设置签到失败时保存调试数据的文件夹
+    pub fn set_debug_dump(&mut self, debug_dump: DebugDump) {
+        self.debug_dump = Some(debug_dump);
+    }
+
+    /// 设置访问海报来源的限速，用于在多个用户之间共享
+    pub fn set_poster_limiter(&mut self, limiter: Arc<RateLimiter>) {
+        self.poster_client.set_limiter(limiter);
+    }
+
+    /// 设置每个选项比较豆瓣前几个搜索结果的海报
+    pub fn set_poster_candidates(&mut self, candidates: usize) {
+        self.poster_client.set_candidates(candidates);
     }
 
     async fn post_answer(&self, value: &str) -> Result<()> {
         let data = &[("answer", value), ("submit", "提交")];
         let r = self
-            .client
-            .post(tjurls::ATTENDANCE)
-            .form(data)
-            .send()
-            .await?
-            .text()
+            .send(transport::post_form(&self.urls.attendance(), data)?)
             .await?;
+        let r = self.read_text("answer", r).await?;
         if r.contains("签到成功") {
             Ok(())
         } else {
-            Err(anyhow!("签到失败"))
+            Err(Error::captcha("签到失败"))
         }
     }
 
-    /// 保存 cookie 到 cookie_path
+    /// 保存 cookie 到 `cookie_storage`
     ///
-    /// 不为 cookiepath 为None 的保存
+    /// 没有设置则不保存
     pub fn save_cookie(&self) -> Result<()> {
-        if let Some(ref cookie_path) = self.cookie_path {
-            let Ok(mut file) = File::create(cookie_path) else {
-                return Err(anyhow!("无法创建cookie文件: {}，请尝试 `--init`", cookie_path.display()));
-            };
-
-            {
-                let lock = self.cookie.lock().map_err(|e| anyhow!("无法获取锁{}", e))?;
-
-                lock.save_json(&mut file)
-                    .map_err(|e| anyhow!("无法写入cookie error: {}", e))?;
-            }
+        if let Some(ref storage) = self.cookie_storage {
+            let lock = self.cookie.lock().expect("cookie 的锁已损坏");
+            storage.save(&lock)?;
         }
         Ok(())
     }
@@ -343,9 +614,297 @@ impl TjuPtUser {
 impl Drop for TjuPtUser {
     fn drop(&mut self) {
         if let Err(e) = self.save_cookie() {
-            log::warn!("{}", e);
+            log::warn!("{}", e.report());
+        }
+    }
+}
+
+/// 创建 [`TjuPtUser`]，不需要配置文件
+///
+/// 未设置的使用默认值: 不保存 cookie，不发送提醒，直接连接 tjupt 及豆瓣
+#[derive(Debug)]
+pub struct TjuPtUserBuilder {
+    config: UserConfig,
+    http: HttpConfig,
+    cookie_storage: Option<Arc<dyn CookieStorage>>,
+    transport: Option<Arc<dyn Transport>>,
+    poster_transport: Option<Arc<dyn Transport>>,
+    site_url: Option<String>,
+    suggest_url: Option<String>,
+    poster_candidates: Option<usize>,
+    poster_limiter: Option<Arc<RateLimiter>>,
+    notifier: Option<Arc<Notifier>>,
+    progress: Option<Progress>,
+    dataset: Option<Dataset>,
+    debug_dump: Option<DebugDump>,
+}
+
+impl TjuPtUserBuilder {
+    /// 用户名及密码
+    pub fn new(id: &str, pwd: &str) -> Self {
+        Self::from_config(UserConfig::new(true, id.into(), pwd.into(), None, None))
+    }
+
+    /// 使用已有的用户配置，包括代理及计算答案的设置
+    pub fn from_config(config: UserConfig) -> Self {
+        Self {
+            config,
+            http: HttpConfig::default(),
+            cookie_storage: None,
+            transport: None,
+            poster_transport: None,
+            site_url: None,
+            suggest_url: None,
+            poster_candidates: None,
+            poster_limiter: None,
+            notifier: None,
+            progress: None,
+            dataset: None,
+            debug_dump: None,
+        }
+    }
+
+    /// 签到失败时提醒的邮箱，需要 `notifier` 开启邮件提醒
+    pub fn email(mut self, email: &str) -> Self {
+        self.config.set_email(Some(email.into()));
+        self
+    }
+
+    /// 签到尝试的次数，默认 3
+    pub fn retry(mut self, retry: u8) -> Self {
+        self.config.set_retry(retry);
+        self
+    }
+
+    /// 保存 cookie 的位置
+    pub fn cookie_storage<S: CookieStorage + 'static>(mut self, storage: S) -> Self {
+        self.cookie_storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// 保存 cookie 到 json 文件
+    pub fn cookie_file<P: AsRef<Path>>(self, path: P) -> Self {
+        self.cookie_storage(FileStorage::new(path))
+    }
+
+    /// 超时、User-Agent、请求头等设置，tjupt 及海报来源都使用
+    pub fn http(mut self, http: HttpConfig) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// 访问 tjupt 使用的代理
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.config.set_proxy(Some(proxy));
+        self
+    }
+
+    /// 访问海报来源使用的代理
+    pub fn poster_proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.config.set_poster_proxy(Some(proxy));
+        self
+    }
+
+    /// 访问 tjupt 使用的 transport，设置后 `http` 及 `proxy` 不再生效
+    ///
+    /// cookie 由 `transport` 自行处理，设置的 `cookie_storage` 会被忽略，不会加载及保存
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// 访问海报来源使用的 transport，设置后 `http` 及 `poster_proxy` 不再生效
+    pub fn poster_transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.poster_transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// tjupt 站点的地址，默认 https://tjupt.org
+    pub fn site_url(mut self, base: &str) -> Self {
+        self.site_url = Some(base.into());
+        self
+    }
+
+    /// 豆瓣搜索建议的链接
+    pub fn suggest_url(mut self, url: &str) -> Self {
+        self.suggest_url = Some(url.into());
+        self
+    }
+
+    /// 相似度阈值，百分制
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.config.set_threshold(threshold);
+        self
+    }
+
+    /// 相似度算法，`weights` 只在 `Ensemble` 时使用，为空则权重相同
+    pub fn metric(mut self, kind: MetricKind, weights: BTreeMap<MetricKind, f64>) -> Self {
+        self.config.set_metric(kind, weights);
+        self
+    }
+
+    /// DSSIM scales
+    pub fn scales(mut self, scales: Vec<f64>) -> Self {
+        self.config.set_scales(scales);
+        self
+    }
+
+    /// 缩放海报时如何处理宽高比
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.config.set_fit(fit);
+        self
+    }
+
+    /// 每个选项比较豆瓣前几个搜索结果的海报
+    pub fn poster_candidates(mut self, candidates: usize) -> Self {
+        self.poster_candidates = Some(candidates);
+        self
+    }
+
+    /// 访问海报来源的限速，可以在多个用户之间共享
+    pub fn poster_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.poster_limiter = Some(limiter);
+        self
+    }
+
+    /// 签到失败时的提醒，可以在多个用户之间共享
+    pub fn notifier(mut self, notifier: Arc<Notifier>) -> Self {
+        self.notifier = Some(notifier);
+        self
+    }
+
+    /// 接收签到过程中的事件
+    pub fn progress(mut self, progress: Progress) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// 保存签到数据
+    pub fn dataset(mut self, dataset: Dataset) -> Self {
+        self.dataset = Some(dataset);
+        self
+    }
+
+    /// 签到失败时保存调试数据
+    pub fn debug_dump(mut self, debug_dump: DebugDump) -> Self {
+        self.debug_dump = Some(debug_dump);
+        self
+    }
+
+    pub fn build(self) -> Result<TjuPtUser> {
+        let cookie = Arc::new(CookieStoreMutex::default());
+        // 自定义的 transport 不使用 `cookie`，保存会覆盖原有的 cookie
+        let cookie_storage = match self.transport {
+            Some(_) => None,
+            None => self.cookie_storage,
+        };
+
+        let (transport, client): (Arc<dyn Transport>, _) = match self.transport {
+            Some(transport) => (transport, None),
+            None => {
+                let client = client_builder(&self.http, self.config.proxy())?
+                    .cookie_provider(cookie.clone())
+                    .build()
+                    .context_as(Error::config, "无法创建 http client")?;
+                (Arc::new(client.clone()), Some(client))
+            }
+        };
+        let poster_transport: Arc<dyn Transport> = match self.poster_transport {
+            Some(transport) => transport,
+            None => Arc::new(
+                client_builder(&self.http, self.config.poster_proxy())?
+                    .build()
+                    .context_as(Error::config, "无法创建 http client")?,
+            ),
+        };
+
+        let mut poster_client = PosterClient::new(poster_transport);
+        if let Some(url) = self.suggest_url {
+            poster_client.set_suggest_url(&url);
+        }
+        if let Some(candidates) = self.poster_candidates {
+            poster_client.set_candidates(candidates);
         }
+        if let Some(limiter) = self.poster_limiter {
+            poster_client.set_limiter(limiter);
+        }
+
+        Ok(TjuPtUser {
+            config: self.config,
+            transport,
+            client,
+            urls: self
+                .site_url
+                .map(|base| SiteUrls::new(&base))
+                .unwrap_or_default(),
+            poster_client,
+            cookie,
+            cookie_storage,
+            notifier: self.notifier.unwrap_or_default(),
+            progress: self.progress,
+            dataset: self.dataset,
+            debug_dump: self.debug_dump,
+            trace: Mutex::default(),
+            tape: None,
+        })
+    }
+}
+
+/// 解析签到页面，获取题图及选项
+///
+/// 题图的链接相对于 `urls`
+pub fn parse_att_html(
+    html: &str,
+    urls: &SiteUrls,
+) -> Result<(picparser::Kaptcha, Vec<picparser::Answer>)> {
+    let doc = Html::parse_document(html);
+
+    // //input[@type="radio"]s
+    let radio = doc.select(&INPUT_RADIO_SELE);
+
+    // 选项们
+    let answers = radio
+        .into_iter()
+        .filter_map(|e| {
+            let name = e.next_sibling()?;
+            let name = name.value().as_text()?;
+            // let name = name.to_string();
+
+            let value = e.value().attr("value")?;
+
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect::<Vec<_>>();
+
+    if answers.is_empty() {
+        // 如果是空的，说明签到完了，或者需要补签
+        return Err(Error::parse("无法找到选项，可能已经签到，或需要补签"));
     }
+
+    // 图片
+    let Some(img) = doc
+        .select(&TD)
+        .next()
+        .and_then(|e| e.select(&IMG).next())
+        .and_then(|e| e.value().attr("src"))
+    else {
+        return Err(Error::parse("无法定位图片"));
+    };
+
+    let img_url = urls.join(img);
+
+    // log::debug!("获取的图片链接: {}", img_url);
+    // 这里检查一下图片应该是jpg结尾的
+    // if !img_url.ends_with(".jpg") {
+    //     return Err(anyhow!("无法获取jpg格式图片"));
+    // }
+
+    // for (x, y) in answers.iter() {
+    //     log::debug!("选项: {}, {}", x, y);
+    // }
+
+    let answers = answers.into_iter().map(picparser::Answer::from).collect();
+    Ok((picparser::Kaptcha::new(img_url), answers))
 }
 
 /// 初始化
@@ -384,13 +943,36 @@ pub fn uninstall() -> Result<()> {
 }
 
 /// 解析命令行参数，并且运行
-pub async fn attendance() -> Result<()> {
-    let mat = crate::cliparser::cli_parser()?;
+///
+/// 返回进程的退出状态
+pub async fn attendance(mat: ArgMatches) -> Result<RunStatus> {
+    let summary = match run(&mat).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            log::error!("Error: {}", e.report());
+            RunSummary::failed(error_status(&e), &e)
+        }
+    };
+
+    if mat.get_one::<String>("output").map(String::as_str) == Some("json") {
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+    }
+    Ok(summary.status)
+}
 
+/// 运行，返回签到结果
+async fn run(mat: &ArgMatches) -> Result<RunSummary> {
     let config_path: &String = mat.get_one("file").unwrap();
 
     let enable_email = mat.get_flag("email");
+    let dry_run = mat.get_flag("dry-run");
+    let replay = mat.get_one::<String>("replay").is_some();
+    let debug_dump = mat.get_one::<String>("debug-dump").map(Path::new);
+    let print_text = mat.get_one::<String>("output").map(String::as_str) != Some("json");
+    let live = mat.get_one::<String>("progress").map(String::as_str) == Some("auto")
+        && std::io::stderr().is_terminal();
 
+    let mut outcomes = vec![];
     if mat.get_flag("init") {
         // 如果是初始化
         crate::bot::initialization()?;
@@ -404,10 +986,12 @@ pub async fn attendance() -> Result<()> {
         let retry: u8 = *mat.get_one("retry").unwrap();
         let mut users_vec = vec![];
         for i in 0..users_num {
-            let Some(user_id) = users.get(2*i)
-                else {continue;};
-            let Some(user_pwd) = users.get(2*i+1)
-                else {continue;};
+            let Some(user_id) = users.get(2 * i) else {
+                continue;
+            };
+            let Some(user_pwd) = users.get(2 * i + 1) else {
+                continue;
+            };
             let user = UserConfig::new(
                 true,
                 user_id.to_string(),
@@ -416,12 +1000,21 @@ pub async fn attendance() -> Result<()> {
                 Some(retry),
             );
 
-            users_vec.push(TjuPtUser::from_config::<&Path>(user, None));
+            let mut user = TjuPtUserBuilder::from_config(user);
+            if let Some(dir) = debug_dump {
+                user = user.debug_dump(DebugDump::new(dir));
+            }
+            users_vec.push(user.build()?);
         }
 
+        set_tapes(&mut users_vec, mat)?;
+
         // 开始马上签到
-        let email_config = Arc::new(EmailConfig::default());
-        att_all_now(users_vec, false, email_config).await;
+        outcomes = if dry_run {
+            dry_run_all(users_vec, print_text).await
+        } else {
+            att_all_now(users_vec, 0, None, live).await
+        };
     } else if let Some(config_mat) = mat.subcommand_matches("config") {
         // 如果是配置文件
         let config_path: &String = config_mat.get_one("file").unwrap();
@@ -429,7 +1022,7 @@ pub async fn attendance() -> Result<()> {
 
         let mut config_file = {
             if config_path.is_file() {
-                ConfigFile::new_from(config_path)?
+                ConfigFile::new_from_unresolved(config_path)?
             } else {
                 ConfigFile::default()
             }
@@ -473,51 +1066,502 @@ pub async fn attendance() -> Result<()> {
             }
             println!("邮件配置信息：{}", config_file.get_email_config());
         }
+    } else if let Some(test_mat) = mat
+        .subcommand_matches("notify")
+        .and_then(|m| m.subcommand_matches("test"))
+    {
+        // 发送测试提醒
+        let config_path: &String = test_mat.get_one("file").unwrap();
+        let config_file = ConfigFile::new_from(config_path)?;
+        let notifier = notifier(&config_file, true)?;
+        let to = test_mat
+            .get_one::<String>("to")
+            .map(String::as_str)
+            .unwrap_or(config_file.gloablconfig().emailconf().sender());
+        let channels = match test_mat.get_one::<String>("channel").map(String::as_str) {
+            Some("email") => vec![Channel::Email],
+            Some("webhook") => vec![Channel::Webhook],
+            _ => notifier.channels(),
+        };
+
+        let notice = Notice {
+            user: "test",
+            to: Some(to),
+            content: "这是一条测试提醒，收到说明提醒设置正确",
+        };
+        let mut failed = false;
+        for channel in channels {
+            match notifier.send(channel, &notice).await {
+                Ok(d) => println!("[OK] {}", d),
+                Err(e) => {
+                    failed = true;
+                    println!("[FAIL] {}: {}", channel, e.report());
+                }
+            }
+        }
+        if failed {
+            return Ok(RunSummary::with_status(RunStatus::Error));
+        }
+    } else if let Some(solve_mat) = mat.subcommand_matches("solve") {
+        // 离线计算答案
+        solve_offline(solve_mat).await?;
+    } else if let Some(bench_mat) = mat.subcommand_matches("bench") {
+        // 基准测试
+        let dir: &String = bench_mat.get_one("dataset").unwrap();
+        let (threshold, metric, fit, desc) = solver_settings(bench_mat)?;
+        let report = dataset::bench(&Dataset::new(dir), threshold, metric, fit).await?;
+        println!("阈值: {}%, {}", threshold, desc);
+        println!("{}", report);
+    } else if let Some(calibrate_mat) = mat.subcommand_matches("calibrate") {
+        // 推荐相似度阈值
+        let dir: &String = calibrate_mat.get_one("dataset").unwrap();
+        let (_, metric, fit, desc) = solver_settings(calibrate_mat)?;
+        let calibration = dataset::calibrate(&Dataset::new(dir), metric, fit).await?;
+        println!("{}", desc);
+        println!("{}", calibration);
+    } else if let Some(doctor_mat) = mat.subcommand_matches("doctor") {
+        // 环境诊断
+        let config_path: &String = doctor_mat.get_one("file").unwrap();
+        let report = doctor::diagnose(Path::new(config_path)).await;
+        println!("{}", report);
+        if report.has_failure() {
+            return Ok(RunSummary::with_status(RunStatus::Error));
+        }
+    } else if let Some(attend_mat) = mat.subcommand_matches("attend") {
+        // 使用配置文件签到
+        let config_path: &String = attend_mat.get_one("file").unwrap();
+        let config_file = ConfigFile::new_from(config_path)?;
+        let mut users = config_users(&config_file, debug_dump)?;
+        set_tapes(&mut users, mat)?;
+        if attend_mat.get_flag("interactive") {
+            if !std::io::stdin().is_terminal() {
+                return Err(Error::config("`--interactive` 需要在终端中运行"));
+            }
+            let protocol: termimg::Protocol = attend_mat
+                .get_one::<String>("image-protocol")
+                .unwrap()
+                .parse()?;
+            outcomes = att_interactive_all(users, protocol).await;
+        } else {
+            let enable_email = attend_mat.get_flag("email");
+            outcomes = att_config_all(&config_file, users, enable_email, replay, live).await?;
+        }
     } else {
         // 其他情况，使用配置文件直接运行
         let config_file = ConfigFile::new_from(config_path)?;
-        let g_conf = config_file.gloablconfig();
-        let users = config_file
-            .get_users()
-            .into_iter()
-            .filter_map(|mut u| {
-                u.update_retry(g_conf);
-                if u.enable() {
-                    Some(TjuPtUser::from_config(u, Some(DIRS.state_dir())))
-                } else {
-                    None
+        let mut users = config_users(&config_file, debug_dump)?;
+        set_tapes(&mut users, mat)?;
+
+        outcomes = if dry_run {
+            dry_run_all(users, print_text).await
+        } else {
+            att_config_all(&config_file, users, enable_email, replay, live).await?
+        };
+    }
+    Ok(RunSummary::new(outcomes))
+}
+
+/// 使用本地的题图及选项计算答案，并输出排名
+async fn solve_offline(mat: &ArgMatches) -> Result<()> {
+    let config_file = optional_config(mat)?;
+    let g_conf = config_file.gloablconfig();
+    let (mut kaptcha, mut answers) = match mat.get_one::<String>("page") {
+        Some(page) => {
+            let page = Path::new(page);
+            let html = std::fs::read_to_string(page)
+                .context(format!("无法读取签到页面: {}", page.display()))?;
+            let (kaptcha, answers) = parse_att_html(&html, &g_conf.site_urls())?;
+            let captcha = match mat.get_one::<String>("captcha") {
+                Some(captcha) => PathBuf::from(captcha),
+                None => {
+                    let name = kaptcha.url.rsplit('/').next().unwrap_or_default();
+                    page.with_file_name(name)
                 }
-            })
-            .collect::<Vec<TjuPtUser>>();
+            };
+            (solve::load_kaptcha(captcha)?, answers)
+        }
+        None => {
+            let captcha: &String = mat.get_one("captcha").unwrap();
+            let answers = mat
+                .get_many::<String>("option")
+                .unwrap()
+                .map(|o| solve::parse_option(o))
+                .collect();
+            (solve::load_kaptcha(captcha)?, answers)
+        }
+    };
+
+    kaptcha.set_metric(similarity::build(
+        g_conf.metric_kind(),
+        &g_conf.metric_weights(),
+        g_conf.scales(),
+    ));
+    kaptcha.set_fit(g_conf.fit());
+    let mut poster_client =
+        PosterClient::new(client_builder(config_file.http(), g_conf.poster_proxy())?.build()?);
+    poster_client.set_limiter(Arc::new(RateLimiter::new(g_conf.poster_interval())));
+    poster_client.set_candidates(g_conf.poster_candidates());
+    poster_client.set_suggest_url(g_conf.suggest_url());
+
+    let cache = match mat.get_one::<String>("posters") {
+        Some(dir) => solve::PosterCache::new(dir),
+        None => solve::PosterCache::new(DIRS.state_dir().join("posters")),
+    };
+    solve::solve(
+        &mut kaptcha,
+        &mut answers,
+        &poster_client,
+        &cache,
+        mat.get_flag("offline"),
+    )
+    .await?;
+
+    println!("题图: {}", kaptcha.url);
+    for (n, a) in solve::ranking(&answers).into_iter().enumerate() {
+        let score = OptionScore {
+            name: a.name.clone(),
+            score: a.score,
+        };
+        println!("    {}. {} ({})", n + 1, score, a.value);
+    }
+    match picparser::pick_answer(&answers, g_conf.threshold()) {
+        Some(a) => println!("将会选择: {}", a.name),
+        None => println!("没有相似度不低于 {}% 的选项", g_conf.threshold()),
+    }
+    Ok(())
+}
+
+/// `-f` 指定的配置文件，不存在时使用默认配置
+fn optional_config(mat: &ArgMatches) -> Result<ConfigFile> {
+    let config_path: &String = mat.get_one("file").unwrap();
+    if Path::new(config_path).is_file() {
+        ConfigFile::new_from(config_path)
+    } else {
+        Ok(ConfigFile::default())
+    }
+}
 
-        // 签到
-        let email_config = Arc::new(config_file.get_email_config());
-        att_all_now(users, enable_email, email_config).await;
+/// 命令行指定的相似度阈值、scales 及算法，未指定的使用配置文件
+///
+/// 返回阈值、算法、缩放方式及算法的描述
+fn solver_settings(mat: &ArgMatches) -> Result<(f64, Arc<dyn SimilarityMetric>, Fit, String)> {
+    let config_file = optional_config(mat)?;
+    let g_conf = config_file.gloablconfig();
+    let threshold = mat
+        .try_get_one::<f64>("threshold")
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or(g_conf.threshold());
+    let scales = match mat.get_many::<f64>("scales") {
+        Some(scales) => scales.copied().collect(),
+        None => g_conf.scales().to_vec(),
+    };
+    let kind = match mat.get_one::<String>("metric") {
+        Some(kind) => kind.parse()?,
+        None => g_conf.metric_kind(),
+    };
+    let weights = g_conf.metric_weights();
+    let metric = similarity::build(kind, &weights, &scales);
+    let fit = g_conf.fit();
+    let desc = match kind {
+        MetricKind::Ensemble => format!(
+            "算法: {} {:?}, scales: {:?}, fit: {}",
+            kind, weights, scales, fit
+        ),
+        _ => format!("算法: {}, scales: {:?}, fit: {}", kind, scales, fit),
+    };
+    Ok((threshold, metric, fit, desc))
+}
+
+/// 命令行指定了 `--record` 或 `--replay` 时，每个用户使用以用户名命名的子文件夹
+fn set_tapes(users: &mut [TjuPtUser], mat: &ArgMatches) -> Result<()> {
+    if let Some(dir) = mat.get_one::<String>("record") {
+        for user in users.iter_mut() {
+            let config = user.config();
+            let tape = Tape::record(Path::new(dir).join(config.id()), &[config.pwd()])?;
+            user.set_tape(tape);
+        }
+    } else if let Some(dir) = mat.get_one::<String>("replay") {
+        for user in users.iter_mut() {
+            let config = user.config();
+            let tape = Tape::replay(Path::new(dir).join(config.id()), &[config.pwd()])?;
+            user.set_tape(tape);
+        }
     }
     Ok(())
 }
 
+/// 配置文件中开启的用户，共享访问海报来源的限速
+///
+/// `debug_dump` 为命令行指定的保存调试数据的文件夹，会覆盖配置文件
+fn config_users(config_file: &ConfigFile, debug_dump: Option<&Path>) -> Result<Vec<TjuPtUser>> {
+    let g_conf = config_file.gloablconfig();
+    let debug_dump = debug_dump.or(g_conf.debug_dump());
+    let poster_limiter = Arc::new(RateLimiter::new(g_conf.poster_interval()));
+    config_file
+        .get_users()
+        .into_iter()
+        .filter_map(|mut u| {
+            u.update_retry(g_conf);
+            u.update_proxy(g_conf);
+            u.update_solver(g_conf);
+            if u.enable() {
+                let cookie_file = DIRS.state_dir().join(format!("{}_cookie.json", u.id()));
+                let mut user = TjuPtUserBuilder::from_config(u)
+                    .http(config_file.http().clone())
+                    .cookie_file(cookie_file)
+                    .poster_limiter(poster_limiter.clone())
+                    .poster_candidates(g_conf.poster_candidates())
+                    .site_url(g_conf.site_urls().base())
+                    .suggest_url(g_conf.suggest_url());
+                if let Some(dir) = g_conf.dataset() {
+                    user = user.dataset(Dataset::new(dir));
+                }
+                if let Some(dir) = debug_dump {
+                    user = user.debug_dump(DebugDump::new(dir));
+                }
+                Some(user.build())
+            } else {
+                None
+            }
+        })
+        .collect::<Result<Vec<TjuPtUser>>>()
+}
+
+/// 使用配置文件中的并发及提醒设置签到
+///
+/// `replay` 为 true 时是回放记录，不发送提醒
+async fn att_config_all(
+    config_file: &ConfigFile,
+    mut users: Vec<TjuPtUser>,
+    enable_email: bool,
+    replay: bool,
+    live: bool,
+) -> Result<Vec<UserOutcome>> {
+    let g_conf = config_file.gloablconfig();
+    let max_concurrency = g_conf.max_concurrency();
+    let stagger = g_conf.stagger();
+    let notifier = if replay {
+        Notifier::default()
+    } else {
+        notifier(config_file, enable_email)?
+    };
+    let notifier = Arc::new(notifier);
+    for user in users.iter_mut() {
+        user.set_notifier(notifier.clone());
+    }
+    Ok(att_all_now(users, max_concurrency, stagger, live).await)
+}
+
+/// 根据配置文件生成提醒
+///
+/// `enable_email` 为 false 时不发送邮件
+fn notifier(config_file: &ConfigFile, enable_email: bool) -> Result<Notifier> {
+    let g_conf = config_file.gloablconfig();
+    let email = enable_email.then(|| g_conf.emailconf().clone());
+    // webhook 是第三方的地址，不经过访问 tjupt 的代理
+    let proxy = g_conf.webhook().and_then(|w| w.proxy());
+    let client = client_builder(config_file.http(), proxy)?
+        .build()
+        .context_as(Error::config, "无法创建 http client")?;
+    Ok(Notifier::new(email, g_conf.webhook().cloned(), client))
+}
+
 /// 批量签到
-async fn att_all_now(users: Vec<TjuPtUser>, enable_email: bool, email_config: Arc<EmailConfig>) {
+///
+/// 最多同时签到 `max_concurrency` 个用户，0 表示不限制
+///
+/// 如果指定了 `stagger`，每两个用户开始之间随机等待其范围内的时间
+async fn att_all_now(
+    mut users: Vec<TjuPtUser>,
+    max_concurrency: usize,
+    stagger: Option<(Duration, Duration)>,
+    live: bool,
+) -> Vec<UserOutcome> {
+    let view = live.then(|| {
+        let ids = users.iter().map(|u| u.config().id()).collect::<Vec<_>>();
+        let (view, progress) = LiveView::start(&ids);
+        for user in users.iter_mut() {
+            user.set_progress(progress.clone());
+        }
+        view
+    });
+
+    let permits = match max_concurrency {
+        0 => Semaphore::MAX_PERMITS,
+        n => n,
+    };
+    let semaphore = Arc::new(Semaphore::new(permits));
+
     // 签到
     let mut hands = vec![];
-    for i in users.into_iter() {
-        let email_config = email_config.clone();
-        hands.push(tokio::spawn(async move {
-            i.att_now(enable_email, email_config).await
-        }));
+    for (n, i) in users.into_iter().enumerate() {
+        if n > 0 {
+            if let Some((min, max)) = stagger {
+                let delay = rand::thread_rng().gen_range(min..=max);
+                log::debug!("等待 {:?} 后开始下一个用户", delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+
+        let Ok(permit) = semaphore.clone().acquire_owned().await else {
+            break;
+        };
+        // 任务 panic 时用来生成失败的结果
+        let id = i.config().id().to_owned();
+        let retry = i.config().retry();
+        let progress = i.progress.clone();
+        if let Some(ref progress) = progress {
+            progress.emit(&id, Event::Started);
+        }
+        let spawned = Instant::now();
+        let hand = tokio::spawn(async move {
+            let _permit = permit;
+            let start = Instant::now();
+            logger::with_user(i.config().id(), async {
+                let res = i.att_now().await;
+                if let Err(ref e) = res {
+                    log::error!("{}", e.report());
+                }
+                UserOutcome::new(i.config().id(), &res, i.config().retry(), start.elapsed())
+            })
+            .await
+        });
+        hands.push((id, retry, progress, spawned, hand));
     }
 
-    for i in hands.into_iter() {
-        let Ok(i) = i.await else {
-            continue;
+    let mut outcomes = vec![];
+    for (id, retry, progress, spawned, hand) in hands.into_iter() {
+        let outcome = match hand.await {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                log::error!("{} 签到异常退出: {}", id, e);
+                let outcome = UserOutcome::panicked(&id, retry, spawned.elapsed(), &e.to_string());
+                // 签到中途退出，不会发出结束的事件
+                if let Some(progress) = progress {
+                    progress.emit(
+                        &id,
+                        Event::Finished {
+                            answer: None,
+                            attempts: retry,
+                            error: outcome.error.clone(),
+                        },
+                    );
+                }
+                outcome
+            }
+        };
+        outcomes.push(outcome);
+    }
+    if let Some(view) = view {
+        view.finish(&outcomes);
+    }
+    outcomes
+}
+
+/// 依次计算每个用户的答案，不提交
+///
+/// `print` 为 true 时在标准输出打印每个选项的相似度及将会选择的答案
+async fn dry_run_all(users: Vec<TjuPtUser>, print: bool) -> Vec<UserOutcome> {
+    let mut outcomes = vec![];
+    for i in users.iter() {
+        let id = i.config().id();
+        let start = Instant::now();
+        let res = logger::with_user(id, i.dry_run()).await;
+        let elapsed = start.elapsed();
+
+        let (res, scores) = match res {
+            Ok(answers) => {
+                let scores = answers
+                    .iter()
+                    .map(|a| OptionScore {
+                        name: a.name.clone(),
+                        score: a.score,
+                    })
+                    .collect::<Vec<_>>();
+                let threshold = i.config().threshold();
+                let res = match picparser::pick_answer(&answers, threshold) {
+                    Some(answer) => Ok(Attended {
+                        answer: answer.name.clone(),
+                        attempts: 1,
+                    }),
+                    None => Err(Error::captcha(format!(
+                        "没有相似度不低于 {}% 的选项",
+                        threshold
+                    ))),
+                };
+                (res, scores)
+            }
+            Err(e) => (Err(e.context(format!("计算答案失败: {}", id))), vec![]),
         };
 
-        if let Err(e) = i {
-            log::error!("{}", e);
+        if print {
+            println!("{}:", id);
+            for s in scores.iter() {
+                println!("    {}", s);
+            }
+            match res {
+                Ok(ref attended) => println!("    将会选择: {}", attended.answer),
+                Err(ref e) => println!("    无法选择: {}", e.report()),
+            }
+        }
+        outcomes.push(UserOutcome::new(id, &res, 1, elapsed).with_scores(scores));
+    }
+    outcomes
+}
+
+/// 从标准输入读取选择的选项，返回从 0 开始的序号
+///
+/// 直接回车使用 `recommended`，输入 q 则返回 `None`
+fn choose_answer(len: usize, recommended: Option<usize>) -> Result<Option<usize>> {
+    let stdin = std::io::stdin();
+    loop {
+        match recommended {
+            Some(r) => print!("请选择答案 [1-{}]，直接回车选择 {}，q 跳过: ", len, r + 1),
+            None => print!("请选择答案 [1-{}]，q 跳过: ", len),
+        }
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.eq_ignore_ascii_case("q") {
+            return Ok(None);
+        }
+        if line.is_empty() {
+            if recommended.is_some() {
+                return Ok(recommended);
+            }
             continue;
         }
+        match line.parse::<usize>() {
+            Ok(n) if (1..=len).contains(&n) => return Ok(Some(n - 1)),
+            _ => println!("无效的选项: {}", line),
+        }
+    }
+}
+
+/// 依次手动签到每个用户
+async fn att_interactive_all(
+    users: Vec<TjuPtUser>,
+    protocol: termimg::Protocol,
+) -> Vec<UserOutcome> {
+    let mut outcomes = vec![];
+    for i in users.iter() {
+        let id = i.config().id();
+        let start = Instant::now();
+        let res = logger::with_user(id, i.att_interactive(protocol)).await;
+        match res {
+            Err(ref e) if e.kind() == ErrorKind::Cancelled => log::info!("{} 已跳过", id),
+            Err(ref e) => log::error!("{} 签到失败: {}", id, e.report()),
+            Ok(_) => {}
+        }
+        outcomes.push(UserOutcome::new(id, &res, 1, start.elapsed()));
     }
+    outcomes
 }
 
 /// 从user——vec转users
@@ -526,10 +1570,10 @@ fn get_users_vec(users: Vec<&str>) -> Vec<(&str, &str)> {
     let mut users_res = vec![];
     for i in 0..users_num {
         // let id = users[2*i];
-        let Some(id) = users.get(2*i) else {
+        let Some(id) = users.get(2 * i) else {
             continue;
         };
-        let Some(pwd) = users.get(2*i+1) else {
+        let Some(pwd) = users.get(2 * i + 1) else {
             continue;
         };
 
@@ -1,16 +1,16 @@
 //! 命令行参数解析
 
 use crate::command::DIRS;
-use anyhow::{anyhow, Result};
+use crate::error::{Error, Result};
 use clap::{
     crate_authors, crate_description, crate_name, crate_version, value_parser, Arg, ArgAction,
-    ArgMatches, Command,
+    ArgGroup, ArgMatches, Command,
 };
 
 /// 使用`clap`来解析命令行参数
 pub fn cli_parser() -> Result<ArgMatches> {
     let Some(config_path) = DIRS.config_path().to_str() else {
-        return Err(anyhow!("无法获取配置文件位置"));
+        return Err(Error::config("无法获取配置文件位置"));
     };
     Ok(Command::new(crate_name!())
         .about(crate_description!())
@@ -125,6 +125,179 @@ AzureQAQ's Blog: https://www.3moredays.com/
                 .num_args(0)
                 .conflicts_with("user"),
         )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("只计算答案，不提交")
+                .long_help(
+                    "\
+登陆并计算每个选项的相似度，输出将会选择的答案
+不会提交答案，也不会发送提醒
+可以与 `-u` 或配置文件一起使用",
+                )
+                .action(ArgAction::SetTrue)
+                .num_args(0),
+        )
+        .arg(
+            Arg::new("debug-dump")
+                .long("debug-dump")
+                .help("签到失败时保存调试数据")
+                .long_help(
+                    "\
+签到失败时保存签到页面、登录的响应、题图、海报及 `trace.json` 到指定文件夹
+每次失败保存在以时间及用户命名的文件夹中，密码及 passkey 会被替换为 ***
+会覆盖配置文件中的 `debug_dump`",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::new("record")
+                .long("record")
+                .help("记录所有请求及响应")
+                .long_help(
+                    "\
+记录访问 tjupt 及海报来源的所有请求及响应到指定文件夹，每个用户一个子文件夹
+密码及 passkey 会被替换为 ***，可以用 `--replay` 回放
+只记录跟随重定向后最终的响应，中间的 302 及其 Set-Cookie 不会保存",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("DIR")
+                .conflicts_with("replay"),
+        )
+        .arg(
+            Arg::new("replay")
+                .long("replay")
+                .help("回放记录的响应，不访问网络")
+                .long_help(
+                    "\
+使用 `--record` 记录的响应代替网络，按顺序返回与请求的方法及链接相同的响应
+用于确定地重现问题，记录中没有的请求会失败
+回放时不发送提醒，不加载及保存 cookie，也不保存到数据集",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("DIR"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .help("签到结果的输出格式")
+                .long_help(
+                    "\
+签到结束后输出结果的格式
+json: 在标准输出打印每个用户的签到结果
+text: 不额外输出，只有日志",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .value_name("FORMAT"),
+        )
+        .arg(
+            Arg::new("progress")
+                .long("progress")
+                .help("签到进度的显示方式")
+                .long_help(
+                    "\
+签到进度的显示方式
+plain: 只输出日志
+auto: 标准错误是终端时每个用户一行显示进度，结束后输出汇总表，日志在结束后输出",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_parser(["plain", "auto"])
+                .default_value("plain")
+                .value_name("MODE"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("输出更详细的日志")
+                .long_help("输出更详细的日志，-v 为 debug，-vv 为 trace")
+                .action(ArgAction::Count)
+                .global(true)
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("输出更少的日志")
+                .long_help("输出更少的日志，-q 为 warn，-qq 为 error，-qqq 不输出")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-filter")
+                .long("log-filter")
+                .help("日志过滤规则")
+                .long_help(
+                    "\
+`RUST_LOG` 格式的日志过滤规则，比如: info,libs::bot=debug,reqwest=warn
+不指定时使用环境变量 `RUST_LOG`
+会覆盖 -v/-q 的设置",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_name("FILTER")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .help("日志格式")
+                .long_help(
+                    "\
+日志格式
+text: 便于阅读
+json: 每行一个json，便于日志收集",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_parser(["text", "json"])
+                .default_value("text")
+                .value_name("FORMAT")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .help("日志写入文件")
+                .long_help(
+                    "\
+日志写入文件而不是标准错误
+指定 PATH 则追加写入此文件
+不指定 PATH 则按本地日期滚动写入状态文件夹下的 logs 文件夹",
+                )
+                .action(ArgAction::Set)
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .value_name("PATH")
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-keep-days")
+                .long("log-keep-days")
+                .help("滚动的日志保留的天数")
+                .long_help(
+                    "\
+按天滚动写入日志时，删除超过 DAYS 天的日志文件
+0 表示不删除",
+                )
+                .action(ArgAction::Set)
+                .num_args(1)
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .value_name("DAYS")
+                .global(true),
+        )
         .subcommand(
             Command::new("config")
                 .about("配置文件相关操作")
@@ -200,5 +373,375 @@ AzureQAQ's Blog: https://www.3moredays.com/
                         .conflicts_with("adduser"),
                 ),
         )
+        .subcommand(
+            Command::new("attend")
+                .about("使用配置文件签到")
+                .long_about(
+                    "\
+使用配置文件签到，与不加子命令直接运行相同
+使用 `--interactive` 时在终端显示题图及每个选项的相似度，手动选择答案",
+                )
+                .arg(
+                    Arg::new("interactive")
+                        .long("interactive")
+                        .short('i')
+                        .help("手动选择答案")
+                        .long_help(
+                            "\
+依次对每个开启的用户签到
+在终端显示题图，列出每个选项及其相似度，由用户选择后提交",
+                        )
+                        .action(ArgAction::SetTrue)
+                        .num_args(0),
+                )
+                .arg(
+                    Arg::new("image-protocol")
+                        .long("image-protocol")
+                        .help("显示题图的方式")
+                        .long_help(
+                            "\
+显示题图的方式
+auto: 根据终端自动选择
+kitty: kitty 图形协议
+sixel: sixel 图形
+blocks: 半块字符，需要终端支持 24 位颜色",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_parser(["auto", "kitty", "sixel", "blocks"])
+                        .default_value("auto")
+                        .value_name("PROTOCOL")
+                        .requires("interactive"),
+                )
+                .arg(
+                    Arg::new("email")
+                        .long("email")
+                        .short('e')
+                        .help("是否开启邮件提醒")
+                        .long_help("是否开启邮件提醒，使用配置文件中的邮件配置")
+                        .action(ArgAction::SetTrue)
+                        .num_args(0)
+                        .conflicts_with("interactive"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("自定义配置文件路径")
+                        .long_help(
+                            "\
+指定自定义的配置文件位置
+否则使用默认值",
+                        )
+                        .default_value(config_path)
+                        .action(ArgAction::Set)
+                        .value_name("PATH")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("notify")
+                .about("提醒相关操作")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("test")
+                        .about("发送测试提醒")
+                        .long_about(
+                            "\
+通过配置文件中的提醒设置发送一条测试提醒
+与签到失败时的提醒使用相同的方式，并输出 smtp 或 http 的响应",
+                        )
+                        .arg(
+                            Arg::new("channel")
+                                .long("channel")
+                                .short('c')
+                                .help("提醒的渠道")
+                                .long_help("提醒的渠道，不指定则使用所有已经设置的渠道")
+                                .action(ArgAction::Set)
+                                .num_args(1)
+                                .value_parser(["email", "webhook"])
+                                .value_name("CHANNEL"),
+                        )
+                        .arg(
+                            Arg::new("to")
+                                .long("to")
+                                .help("邮件收件人")
+                                .long_help("邮件收件人，不指定则发送给邮件配置中的发件人")
+                                .action(ArgAction::Set)
+                                .num_args(1)
+                                .value_name("ADDR"),
+                        )
+                        .arg(
+                            Arg::new("file")
+                                .short('f')
+                                .long("file")
+                                .help("自定义配置文件路径")
+                                .long_help(
+                                    "\
+指定自定义的配置文件位置
+否则使用默认值",
+                                )
+                                .default_value(config_path)
+                                .action(ArgAction::Set)
+                                .value_name("PATH")
+                                .num_args(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("solve")
+                .about("离线计算答案")
+                .long_about(
+                    "\
+使用保存在本地的题图及选项计算答案，不会访问 tjupt
+按相似度从高到低输出每个选项，用于检查答案计算的改动
+海报优先从本地缓存读取，获取到的海报会写入缓存",
+                )
+                .arg(
+                    Arg::new("captcha")
+                        .long("captcha")
+                        .short('c')
+                        .help("题图")
+                        .long_help(
+                            "\
+保存在本地的题图
+使用 `--page` 时可以不指定，此时使用签到页面所在文件夹中同名的图片",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_name("PATH"),
+                )
+                .arg(
+                    Arg::new("option")
+                        .long("option")
+                        .help("选项, <TITLE>=<VALUE>")
+                        .long_help(
+                            "\
+选项，格式为: <TITLE>=<VALUE>
+可以通过 --option <a> --option <b> ... 指定多个",
+                        )
+                        .action(ArgAction::Append)
+                        .num_args(1)
+                        .value_name("TITLE=VALUE")
+                        .requires("captcha"),
+                )
+                .arg(
+                    Arg::new("page")
+                        .long("page")
+                        .help("保存的签到页面")
+                        .long_help("保存的签到页面，从中解析题图及选项")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_name("PATH"),
+                )
+                .group(
+                    ArgGroup::new("options")
+                        .args(["option", "page"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("posters")
+                        .long("posters")
+                        .help("海报缓存文件夹")
+                        .long_help("海报缓存文件夹，不指定则使用状态文件夹下的 posters")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_name("DIR"),
+                )
+                .arg(
+                    Arg::new("offline")
+                        .long("offline")
+                        .help("只使用缓存的海报")
+                        .long_help("不访问海报来源，没有缓存海报的选项不会计算相似度")
+                        .action(ArgAction::SetTrue)
+                        .num_args(0),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("自定义配置文件路径")
+                        .long_help(
+                            "\
+指定自定义的配置文件位置，使用其中的 http 及海报代理设置
+文件不存在时使用默认设置",
+                        )
+                        .default_value(config_path)
+                        .action(ArgAction::Set)
+                        .value_name("PATH")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("答案计算的基准测试")
+                .long_about(
+                    "\
+使用数据集中保存的题图及海报重新计算答案，不会访问网络
+输出准确率、误选率及耗时
+数据集通过配置文件中的 `dataset` 收集",
+                )
+                .arg(
+                    Arg::new("dataset")
+                        .long("dataset")
+                        .short('d')
+                        .help("数据集文件夹")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_name("DIR")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("threshold")
+                        .long("threshold")
+                        .short('t')
+                        .help("相似度阈值，百分制")
+                        .long_help(
+                            "\
+相似度不低于此值的第一个选项会被选择，百分制
+不指定则使用配置文件中的设置，默认 93",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_parser(value_parser!(f64))
+                        .value_name("PERCENT"),
+                )
+                .arg(
+                    Arg::new("metric")
+                        .long("metric")
+                        .short('m')
+                        .help("相似度算法")
+                        .long_help(
+                            "\
+相似度算法，不指定则使用配置文件中的设置
+dssim、phash、dhash、histogram 或 ensemble",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_parser(["dssim", "phash", "dhash", "histogram", "ensemble"])
+                        .value_name("METRIC"),
+                )
+                .arg(
+                    Arg::new("scales")
+                        .long("scales")
+                        .short('s')
+                        .help("DSSIM scales")
+                        .long_help(
+                            "\
+DSSIM 每一层的权重，用逗号分隔，比如: 100,100
+不指定则使用配置文件中的设置",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .value_parser(value_parser!(f64))
+                        .value_name("SCALES"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("自定义配置文件路径")
+                        .long_help(
+                            "\
+指定自定义的配置文件位置，使用其中的相似度阈值及 scales
+文件不存在时使用默认设置",
+                        )
+                        .default_value(config_path)
+                        .action(ArgAction::Set)
+                        .value_name("PATH")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("calibrate")
+                .about("推荐相似度阈值")
+                .long_about(
+                    "\
+使用数据集中已知对错的样本重新计算相似度
+推荐一个能最好地区分正确与错误选项的阈值",
+                )
+                .arg(
+                    Arg::new("dataset")
+                        .long("dataset")
+                        .short('d')
+                        .help("数据集文件夹")
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_name("DIR")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("metric")
+                        .long("metric")
+                        .short('m')
+                        .help("相似度算法")
+                        .long_help(
+                            "\
+相似度算法，不指定则使用配置文件中的设置
+dssim、phash、dhash、histogram 或 ensemble",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_parser(["dssim", "phash", "dhash", "histogram", "ensemble"])
+                        .value_name("METRIC"),
+                )
+                .arg(
+                    Arg::new("scales")
+                        .long("scales")
+                        .short('s')
+                        .help("DSSIM scales")
+                        .long_help(
+                            "\
+DSSIM 每一层的权重，用逗号分隔，比如: 100,100
+不指定则使用配置文件中的设置",
+                        )
+                        .action(ArgAction::Set)
+                        .num_args(1)
+                        .value_delimiter(',')
+                        .value_parser(value_parser!(f64))
+                        .value_name("SCALES"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("自定义配置文件路径")
+                        .long_help(
+                            "\
+指定自定义的配置文件位置，使用其中的 scales
+文件不存在时使用默认设置",
+                        )
+                        .default_value(config_path)
+                        .action(ArgAction::Set)
+                        .value_name("PATH")
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("环境诊断")
+                .long_about(
+                    "\
+检查运行环境，并输出检查报告
+包括: 文件夹是否可写、配置文件能否解析、密码能否解析、文件权限、
+tjupt 及豆瓣能否访问、保存的cookie是否有效、邮件能否登陆(不会发送邮件)",
+                )
+                .arg(
+                    Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .help("自定义配置文件路径")
+                        .long_help(
+                            "\
+指定自定义的配置文件位置
+否则使用默认值",
+                        )
+                        .default_value(config_path)
+                        .action(ArgAction::Set)
+                        .value_name("PATH")
+                        .num_args(1),
+                ),
+        )
         .get_matches())
 }
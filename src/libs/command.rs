@@ -9,12 +9,64 @@ pub const CONFIG_FILENAME: &str = "config.toml";
 
 /// 链接们
 pub mod tjurls {
+    /// 默认的站点地址
+    pub const BASE: &str = "https://tjupt.org";
+}
+
+/// 豆瓣的链接
+pub mod doubanurls {
+    /// 搜索建议，返回包含海报的 json
+    pub const SUBJECT_SUGGEST: &str = "https://movie.douban.com/j/subject_suggest";
+}
+
+/// tjupt 站点的链接，默认是 [`tjurls::BASE`]，可以指向测试用的服务器
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SiteUrls {
+    base: String,
+}
+
+impl SiteUrls {
+    pub fn new(base: &str) -> Self {
+        Self {
+            base: base.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
     /// 登陆链接
-    pub const LOGIN: &str = "https://tjupt.org/login.php";
+    pub fn login(&self) -> String {
+        self.join("/login.php")
+    }
+
     /// 签到页面
-    pub const ATTENDANCE: &str = "https://tjupt.org/attendance.php";
+    pub fn attendance(&self) -> String {
+        self.join("/attendance.php")
+    }
+
     /// post的登陆url
-    pub const TAKELOGIN: &str = "https://tjupt.org/takelogin.php";
+    pub fn takelogin(&self) -> String {
+        self.join("/takelogin.php")
+    }
+
+    /// 站内的路径转换为链接，已经是完整链接的不变
+    pub fn join(&self, path: &str) -> String {
+        if path.starts_with("http://") || path.starts_with("https://") {
+            path.to_owned()
+        } else if path.starts_with('/') {
+            format!("{}{}", self.base, path)
+        } else {
+            format!("{}/{}", self.base, path)
+        }
+    }
+}
+
+impl Default for SiteUrls {
+    fn default() -> Self {
+        Self::new(tjurls::BASE)
+    }
 }
 
 /// 文件位置配置
@@ -1,14 +1,22 @@
 //! 配置文件解析
 use std::{
+    collections::BTreeMap,
     fmt::Display,
     fs::{read_to_string, File},
     hash::Hash,
     io::Write,
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
+use crate::command::{doubanurls, SiteUrls};
+use crate::douban::DEFAULT_CANDIDATES;
+use crate::error::{Context, Error, Result};
+use crate::picparser::{Fit, DEFAULT_THRESHOLD};
+use crate::secret::Secret;
+use crate::similarity::{self, MetricKind, SimilarityMetric, DEFAULT_SCALES};
 use ahash::AHashSet;
-use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
 use toml;
 
@@ -16,6 +24,9 @@ use toml;
 pub struct ConfigFile {
     users: AHashSet<UserConfig>,
     global: GlobalConfig,
+    /// http client 设置，tjupt 及海报来源共用
+    #[serde(default)]
+    http: HttpConfig,
 }
 
 impl ConfigFile {
@@ -31,20 +42,77 @@ impl ConfigFile {
     }
 
     /// 从文件读取
+    ///
+    /// 会解析密码中引用的外部来源，见 [`Secret::resolve`]
     pub fn new_from<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut result = Self::new_from_unresolved(path)?;
+        result.resolve_secrets()?;
+        Ok(result)
+    }
+
+    /// 从文件读取，但不解析密码的外部来源
+    ///
+    /// 仅修改配置文件时使用
+    pub fn new_from_unresolved<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        let content = read_to_string(path).context(anyhow!(
-            "无法读取配置文件: {}, 如果使用默认位置则可能需要先初始化 `--init` \
-            请使用`--help`查看更多信息",
-            path.display()
-        ))?;
+        let content = read_to_string(path).context_as(
+            Error::config,
+            format!(
+                "无法读取配置文件: {}, 如果使用默认位置则可能需要先初始化 `--init` \
+                请使用`--help`查看更多信息",
+                path.display()
+            ),
+        )?;
         let result = toml::from_str(&content)?;
         Ok(result)
     }
 
+    /// 解析所有密码的外部来源
+    ///
+    /// 未开启的用户不会解析
+    ///
+    /// 解析后的结果只保存在内存中，不会被 `write_to_file` 写回
+    fn resolve_secrets(&mut self) -> Result<()> {
+        self.users = self
+            .users
+            .drain()
+            .map(|mut u| {
+                if u.enable {
+                    u.pwd_resolved = Some(
+                        u.pwd
+                            .resolve()
+                            .context(format!("无法解析用户 {} 的密码", u.id))?,
+                    );
+                    for proxy in [&mut u.proxy, &mut u.poster_proxy].into_iter().flatten() {
+                        proxy
+                            .resolve_secrets()
+                            .context(format!("无法解析用户 {} 的代理密码", u.id))?;
+                    }
+                }
+                Ok(u)
+            })
+            .collect::<Result<_>>()?;
+
+        // 邮件密码在发送邮件时才解析，不使用邮件提醒时不会执行 `cmd:` 或要求环境变量存在
+        let global = &mut self.global;
+        for proxy in [&mut global.proxy, &mut global.poster_proxy]
+            .into_iter()
+            .flatten()
+        {
+            proxy.resolve_secrets().context("无法解析全局代理密码")?;
+        }
+        if let Some(ref mut webhook) = global.webhook {
+            webhook.url_resolved = Some(webhook.url.resolve().context("无法解析 webhook")?);
+        }
+        Ok(())
+    }
+
     /// 用户配置
     pub fn users(&self) -> &AHashSet<UserConfig> {
         &self.users
@@ -69,7 +137,7 @@ impl ConfigFile {
             log::info!("创建配置文件并写入: {}", path.display());
         }
         let content = toml::to_string(self)?;
-        let mut file = File::create(path).context(anyhow!(
+        let mut file = File::create(path).context(format!(
             "无法创建配置文件文件: {}，可能需要先初始化",
             path.display()
         ))?;
@@ -81,6 +149,11 @@ impl ConfigFile {
         &self.global
     }
 
+    /// http client 设置
+    pub fn http(&self) -> &HttpConfig {
+        &self.http
+    }
+
     /// 增加用户
     pub fn addusers(&mut self, users: Vec<UserConfig>) {
         for i in users.into_iter() {
@@ -124,18 +197,40 @@ impl Default for ConfigFile {
         let mut users = AHashSet::new();
         let _r = users.insert(UserConfig::default());
         let global = GlobalConfig::default();
-        Self { users, global }
+        Self {
+            users,
+            global,
+            http: HttpConfig::default(),
+        }
     }
 }
 
 /// 用户配置信息
-#[derive(Serialize, Deserialize, Eq, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserConfig {
     enable: bool,
     id: String,
-    pwd: String,
+    /// 可以是明文，也可以是外部来源，见 [`Secret::resolve`]
+    pwd: Secret,
+    /// 解析后的密码，不会被写入配置文件
+    #[serde(skip)]
+    pwd_resolved: Option<Secret>,
     email: Option<String>,
     retry: Option<u8>,
+    /// 访问 tjupt 使用的代理，不指定则使用全局配置
+    proxy: Option<ProxyConfig>,
+    /// 访问海报来源(豆瓣)使用的代理，不指定则使用全局配置
+    poster_proxy: Option<ProxyConfig>,
+    /// 相似度阈值，不指定则使用全局配置
+    threshold: Option<f64>,
+    /// DSSIM scales，不指定则使用全局配置
+    scales: Option<Vec<f64>>,
+    /// 相似度算法，不指定则使用全局配置
+    metric: Option<MetricKind>,
+    /// `ensemble` 中各算法的权重，不指定则使用全局配置
+    metric_weights: Option<BTreeMap<MetricKind, f64>>,
+    /// 缩放时如何处理宽高比，不指定则使用全局配置
+    fit: Option<Fit>,
 }
 
 impl PartialEq for UserConfig {
@@ -144,6 +239,8 @@ impl PartialEq for UserConfig {
     }
 }
 
+impl Eq for UserConfig {}
+
 impl Hash for UserConfig {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.id().hash(state);
@@ -165,8 +262,9 @@ impl UserConfig {
         }
     }
 
+    /// 密码，如果已经解析过外部来源则返回解析后的结果
     pub fn pwd(&self) -> &str {
-        self.pwd.as_ref()
+        self.pwd_resolved.as_ref().unwrap_or(&self.pwd).expose()
     }
 
     /// 更新delay
@@ -179,6 +277,73 @@ impl UserConfig {
         })
     }
 
+    /// 更新代理设置，未指定的使用全局配置
+    ///
+    /// 配置文件操作时不能用
+    pub fn update_proxy(&mut self, global_conf: &GlobalConfig) {
+        if self.proxy.is_none() {
+            self.proxy = global_conf.proxy.clone();
+        }
+        if self.poster_proxy.is_none() {
+            self.poster_proxy = global_conf.poster_proxy.clone();
+        }
+    }
+
+    /// 更新相似度阈值、scales 及算法，未指定的使用全局配置
+    ///
+    /// 配置文件操作时不能用
+    pub fn update_solver(&mut self, global_conf: &GlobalConfig) {
+        if self.threshold.is_none() {
+            self.threshold = global_conf.threshold;
+        }
+        if self.scales.is_none() {
+            self.scales = global_conf.scales.clone();
+        }
+        if self.metric.is_none() {
+            self.metric = global_conf.metric;
+        }
+        if self.metric_weights.is_none() {
+            self.metric_weights = global_conf.metric_weights.clone();
+        }
+        if self.fit.is_none() {
+            self.fit = global_conf.fit;
+        }
+    }
+
+    /// 访问 tjupt 使用的代理
+    pub fn proxy(&self) -> Option<&ProxyConfig> {
+        self.proxy.as_ref()
+    }
+
+    /// 访问海报来源使用的代理
+    pub fn poster_proxy(&self) -> Option<&ProxyConfig> {
+        self.poster_proxy.as_ref()
+    }
+
+    /// 相似度阈值，百分制
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(DEFAULT_THRESHOLD)
+    }
+
+    /// DSSIM scales
+    pub fn scales(&self) -> &[f64] {
+        self.scales.as_deref().unwrap_or(&DEFAULT_SCALES)
+    }
+
+    /// 相似度算法
+    pub fn metric(&self) -> Arc<dyn SimilarityMetric> {
+        similarity::build(
+            self.metric.unwrap_or(MetricKind::Dssim),
+            self.metric_weights.as_ref().unwrap_or(&BTreeMap::new()),
+            self.scales(),
+        )
+    }
+
+    /// 缩放时如何处理宽高比
+    pub fn fit(&self) -> Fit {
+        self.fit.unwrap_or_default()
+    }
+
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -200,15 +365,58 @@ impl UserConfig {
         Self {
             enable,
             id,
-            pwd,
+            pwd: pwd.into(),
+            pwd_resolved: None,
             email,
             retry,
+            proxy: None,
+            poster_proxy: None,
+            threshold: None,
+            scales: None,
+            metric: None,
+            metric_weights: None,
+            fit: None,
         }
     }
 
     pub fn email(&self) -> Option<&str> {
         self.email.as_deref()
     }
+
+    /// 签到失败时提醒的邮箱
+    pub fn set_email(&mut self, email: Option<String>) {
+        self.email = email;
+    }
+
+    pub fn set_retry(&mut self, retry: u8) {
+        self.retry = Some(retry);
+    }
+
+    pub fn set_proxy(&mut self, proxy: Option<ProxyConfig>) {
+        self.proxy = proxy;
+    }
+
+    pub fn set_poster_proxy(&mut self, proxy: Option<ProxyConfig>) {
+        self.poster_proxy = proxy;
+    }
+
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = Some(threshold);
+    }
+
+    pub fn set_scales(&mut self, scales: Vec<f64>) {
+        self.scales = Some(scales);
+    }
+
+    /// `weights` 只在 `Ensemble` 时使用
+    pub fn set_metric(&mut self, kind: MetricKind, weights: BTreeMap<MetricKind, f64>) {
+        self.metric = Some(kind);
+        self.metric_weights = Some(weights);
+    }
+
+    pub fn set_fit(&mut self, fit: Fit) {
+        self.fit = Some(fit);
+    }
 }
 
 impl Display for UserConfig {
@@ -223,23 +431,42 @@ impl Default for UserConfig {
             enable: false,
             id: "test".into(),
             pwd: "pwd".into(),
+            pwd_resolved: None,
             email: None,
             retry: None,
+            proxy: None,
+            poster_proxy: None,
+            threshold: None,
+            scales: None,
+            metric: None,
+            metric_weights: None,
+            fit: None,
         }
     }
 }
 
 /// 全局配置里的邮件配置
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EmailConfig {
     user: String,
-    pwd: String,
+    /// 可以是明文，也可以是外部来源，见 [`Secret::resolve`]
+    pwd: Secret,
     sender: Option<String>,
     port: Option<u32>,
     host: Option<String>,
 }
 
 impl EmailConfig {
+    pub fn new(host: &str, port: u32, user: &str, pwd: &str) -> Self {
+        Self {
+            user: user.into(),
+            pwd: pwd.into(),
+            sender: None,
+            port: Some(port),
+            host: Some(host.into()),
+        }
+    }
+
     /// port 默认是 465
     pub fn port(&self) -> u32 {
         self.port.unwrap_or(465)
@@ -264,8 +491,11 @@ impl EmailConfig {
         &self.user
     }
 
-    pub fn pwd(&self) -> &str {
-        &self.pwd
+    /// 密码，每次调用时解析外部来源，只在发送邮件时使用
+    pub fn pwd(&self) -> Result<Secret> {
+        self.pwd
+            .resolve()
+            .context_as(Error::config, "无法解析邮件配置的密码")
     }
 }
 
@@ -287,11 +517,257 @@ impl Default for EmailConfig {
     }
 }
 
+/// 代理配置
+///
+/// 同时指定多个时，`socks5` 优先于 `http` 和 `https`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct ProxyConfig {
+    /// http 请求使用的代理，比如 `http://127.0.0.1:8080`
+    http: Option<String>,
+    /// https 请求使用的代理
+    https: Option<String>,
+    /// 所有请求都使用的 socks5 代理，比如 `socks5://127.0.0.1:1080`
+    ///
+    /// 使用 `socks5h://` 让代理解析域名
+    socks5: Option<String>,
+    /// 代理认证的用户名
+    user: Option<String>,
+    /// 代理认证的密码，支持外部来源，见 [`Secret::resolve`]
+    pwd: Option<Secret>,
+    /// 解析后的密码，不会被写入配置文件
+    #[serde(skip)]
+    pwd_resolved: Option<Secret>,
+}
+
+impl ProxyConfig {
+    /// 所有请求使用 `url` 代理，`socks5://` 或 `socks5h://` 开头的是 socks5 代理
+    pub fn new(url: &str) -> Self {
+        if url.starts_with("socks5") {
+            Self {
+                socks5: Some(url.into()),
+                ..Default::default()
+            }
+        } else {
+            Self {
+                http: Some(url.into()),
+                https: Some(url.into()),
+                ..Default::default()
+            }
+        }
+    }
+
+    /// 设置代理认证
+    pub fn set_auth(&mut self, user: &str, pwd: &str) {
+        self.user = Some(user.into());
+        self.pwd = Some(pwd.into());
+        self.pwd_resolved = None;
+    }
+
+    /// 解析密码的外部来源
+    fn resolve_secrets(&mut self) -> Result<()> {
+        if let Some(ref pwd) = self.pwd {
+            self.pwd_resolved = Some(pwd.resolve()?);
+        }
+        Ok(())
+    }
+
+    /// 转换为 `reqwest` 的代理
+    pub fn proxies(&self) -> Result<Vec<reqwest::Proxy>> {
+        let mut proxies = vec![];
+        if let Some(ref url) = self.socks5 {
+            proxies.push(reqwest::Proxy::all(url).context_as(Error::config, "无效的代理")?);
+        } else {
+            if let Some(ref url) = self.http {
+                proxies.push(reqwest::Proxy::http(url).context_as(Error::config, "无效的代理")?);
+            }
+            if let Some(ref url) = self.https {
+                proxies.push(reqwest::Proxy::https(url).context_as(Error::config, "无效的代理")?);
+            }
+        }
+
+        if let Some(ref user) = self.user {
+            let pwd = self
+                .pwd_resolved
+                .as_ref()
+                .or(self.pwd.as_ref())
+                .map(|p| p.expose())
+                .unwrap_or_default();
+            proxies = proxies
+                .into_iter()
+                .map(|p| p.basic_auth(user, pwd))
+                .collect();
+        }
+        Ok(proxies)
+    }
+}
+
+impl Display for ProxyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let none = "-";
+        write!(
+            f,
+            "Proxy[http: {} https: {} socks5: {}]",
+            self.http.as_deref().unwrap_or(none),
+            self.https.as_deref().unwrap_or(none),
+            self.socks5.as_deref().unwrap_or(none),
+        )
+    }
+}
+
+/// webhook 提醒设置
+///
+/// 会 POST 一个 json: `{"title": ..., "user": ..., "content": ...}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    /// 可能包含 token，支持外部来源，见 [`Secret::resolve`]
+    url: Secret,
+    /// 解析后的 url，不会被写入配置文件
+    #[serde(skip)]
+    url_resolved: Option<Secret>,
+    /// 发送 webhook 使用的代理，不指定则直接连接，不使用访问 tjupt 的代理
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    proxy: Option<ProxyConfig>,
+}
+
+impl WebhookConfig {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.into(),
+            url_resolved: None,
+            proxy: None,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        self.url_resolved.as_ref().unwrap_or(&self.url).expose()
+    }
+
+    pub fn proxy(&self) -> Option<&ProxyConfig> {
+        self.proxy.as_ref()
+    }
+
+    pub fn set_proxy(&mut self, proxy: Option<ProxyConfig>) {
+        self.proxy = proxy;
+    }
+}
+
+/// 强制使用的 IP 版本
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpVersion {
+    V4,
+    V6,
+}
+
+/// http client 设置
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// 连接超时，单位秒，默认 5
+    connect_timeout: Option<u64>,
+    /// 整个请求(包含读取响应)的超时，单位秒，默认 5
+    ///
+    /// reqwest 0.11 不支持单独的读取超时，只能限制整个请求
+    timeout: Option<u64>,
+    /// User-Agent，默认模拟 Chrome
+    user_agent: Option<String>,
+    /// 额外的请求头
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    /// 最多重定向次数，默认 3
+    redirect_limit: Option<usize>,
+    /// 额外信任的 CA 证书，PEM 格式，可以包含多个
+    ca_file: Option<PathBuf>,
+    /// 强制使用 IPv4 或 IPv6，默认不限制
+    ip_version: Option<IpVersion>,
+}
+
+impl HttpConfig {
+    pub fn connect_timeout(&self) -> u64 {
+        self.connect_timeout.unwrap_or(5)
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout.unwrap_or(5)
+    }
+
+    pub fn user_agent(&self) -> &str {
+        self.user_agent
+            .as_deref()
+            .unwrap_or(crate::http::DEFAULT_USER_AGENT)
+    }
+
+    pub fn headers(&self) -> &BTreeMap<String, String> {
+        &self.headers
+    }
+
+    pub fn redirect_limit(&self) -> usize {
+        self.redirect_limit.unwrap_or(3)
+    }
+
+    pub fn ca_file(&self) -> Option<&Path> {
+        self.ca_file.as_deref()
+    }
+
+    pub fn ip_version(&self) -> Option<IpVersion> {
+        self.ip_version
+    }
+
+    pub fn set_connect_timeout(&mut self, secs: u64) {
+        self.connect_timeout = Some(secs);
+    }
+
+    pub fn set_timeout(&mut self, secs: u64) {
+        self.timeout = Some(secs);
+    }
+
+    pub fn set_user_agent(&mut self, user_agent: &str) {
+        self.user_agent = Some(user_agent.into());
+    }
+
+    /// 增加一个请求头，同名的会被替换
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.insert(name.into(), value.into());
+    }
+}
+
 /// 全局配置
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GlobalConfig {
     retry: u8,
     emailconf: EmailConfig,
+    /// 访问 tjupt 使用的代理
+    proxy: Option<ProxyConfig>,
+    /// 访问海报来源(豆瓣)使用的代理
+    poster_proxy: Option<ProxyConfig>,
+    /// 最多同时签到的用户数，不指定或 0 表示不限制
+    max_concurrency: Option<usize>,
+    /// 每两个用户开始签到之间随机等待的时间范围，单位秒，比如 `[1, 5]`
+    stagger: Option<[f64; 2]>,
+    /// 所有用户访问海报来源(豆瓣)的最小间隔，单位毫秒，默认 500
+    poster_interval: Option<u64>,
+    /// 每个选项比较豆瓣前几个搜索结果的海报，默认 1
+    poster_candidates: Option<usize>,
+    /// webhook 提醒，不指定则不发送
+    webhook: Option<WebhookConfig>,
+    /// 保存每次签到的题图、选项、海报及结果的文件夹，不指定则不保存
+    dataset: Option<PathBuf>,
+    /// tjupt 站点的地址，默认 https://tjupt.org，可以指向测试用的服务器
+    site_url: Option<String>,
+    /// 豆瓣搜索建议的链接，默认 https://movie.douban.com/j/subject_suggest
+    suggest_url: Option<String>,
+    /// 签到失败时保存签到页面、登录的响应、题图、海报及 `trace.json` 的文件夹，不指定则不保存
+    debug_dump: Option<PathBuf>,
+    /// 相似度不低于此值的第一个选项会被选择，百分制，默认 93
+    threshold: Option<f64>,
+    /// DSSIM 每一层的权重，默认 `[100, 100]`
+    scales: Option<Vec<f64>>,
+    /// 相似度算法，默认 dssim
+    metric: Option<MetricKind>,
+    /// `ensemble` 中各算法的权重，不指定则权重相同
+    metric_weights: Option<BTreeMap<MetricKind, f64>>,
+    /// 缩放时如何处理宽高比，默认 stretch
+    fit: Option<Fit>,
 }
 
 impl GlobalConfig {
@@ -304,6 +780,98 @@ impl GlobalConfig {
     pub fn emailconf(&self) -> &EmailConfig {
         &self.emailconf
     }
+
+    /// 访问 tjupt 使用的代理
+    pub fn proxy(&self) -> Option<&ProxyConfig> {
+        self.proxy.as_ref()
+    }
+
+    /// 访问海报来源使用的代理
+    pub fn poster_proxy(&self) -> Option<&ProxyConfig> {
+        self.poster_proxy.as_ref()
+    }
+
+    /// webhook 提醒
+    pub fn webhook(&self) -> Option<&WebhookConfig> {
+        self.webhook.as_ref()
+    }
+
+    /// 最多同时签到的用户数，0 表示不限制
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency.unwrap_or(0)
+    }
+
+    /// 每两个用户开始签到之间随机等待的时间范围
+    ///
+    /// 如果给出的范围是反的，会交换顺序，负数视为 0
+    pub fn stagger(&self) -> Option<(Duration, Duration)> {
+        let [a, b] = self.stagger?;
+        let (a, b) = (
+            Duration::from_secs_f64(a.max(0.0)),
+            Duration::from_secs_f64(b.max(0.0)),
+        );
+        Some((a.min(b), a.max(b)))
+    }
+
+    /// 所有用户访问海报来源的最小间隔
+    pub fn poster_interval(&self) -> Duration {
+        Duration::from_millis(self.poster_interval.unwrap_or(500))
+    }
+
+    /// 每个选项比较豆瓣前几个搜索结果的海报
+    pub fn poster_candidates(&self) -> usize {
+        self.poster_candidates.unwrap_or(DEFAULT_CANDIDATES)
+    }
+
+    /// 保存签到数据的文件夹
+    pub fn dataset(&self) -> Option<&Path> {
+        self.dataset.as_deref()
+    }
+
+    /// tjupt 站点的链接
+    pub fn site_urls(&self) -> SiteUrls {
+        self.site_url
+            .as_deref()
+            .map(SiteUrls::new)
+            .unwrap_or_default()
+    }
+
+    /// 豆瓣搜索建议的链接
+    pub fn suggest_url(&self) -> &str {
+        self.suggest_url
+            .as_deref()
+            .unwrap_or(doubanurls::SUBJECT_SUGGEST)
+    }
+
+    /// 保存调试数据的文件夹
+    pub fn debug_dump(&self) -> Option<&Path> {
+        self.debug_dump.as_deref()
+    }
+
+    /// 相似度阈值，百分制
+    pub fn threshold(&self) -> f64 {
+        self.threshold.unwrap_or(DEFAULT_THRESHOLD)
+    }
+
+    /// DSSIM scales
+    pub fn scales(&self) -> &[f64] {
+        self.scales.as_deref().unwrap_or(&DEFAULT_SCALES)
+    }
+
+    /// 相似度算法的种类
+    pub fn metric_kind(&self) -> MetricKind {
+        self.metric.unwrap_or(MetricKind::Dssim)
+    }
+
+    /// `ensemble` 中各算法的权重，为空表示权重相同
+    pub fn metric_weights(&self) -> BTreeMap<MetricKind, f64> {
+        self.metric_weights.clone().unwrap_or_default()
+    }
+
+    /// 缩放时如何处理宽高比
+    pub fn fit(&self) -> Fit {
+        self.fit.unwrap_or_default()
+    }
 }
 
 impl Display for GlobalConfig {
@@ -322,6 +890,119 @@ impl Default for GlobalConfig {
         Self {
             retry: 1,
             emailconf: EmailConfig::default(),
+            proxy: None,
+            poster_proxy: None,
+            max_concurrency: None,
+            stagger: None,
+            poster_interval: None,
+            poster_candidates: None,
+            webhook: None,
+            dataset: None,
+            site_url: None,
+            suggest_url: None,
+            debug_dump: None,
+            threshold: None,
+            scales: None,
+            metric: None,
+            metric_weights: None,
+            fit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+
+    #[test]
+    fn secret_not_written_back() {
+        let mut user = UserConfig::new(true, "id".into(), "env:HOME".into(), None, None);
+        user.pwd_resolved = Some("s3cr3t".into());
+        assert_eq!(user.pwd(), "s3cr3t");
+        assert!(!format!("{:?}", user).contains("s3cr3t"));
+        let content = toml::to_string(&user).unwrap();
+        assert!(content.contains("env:HOME"));
+        assert!(!content.contains("s3cr3t"));
+    }
+
+    #[test]
+    fn proxy_inherit_test() {
+        let content = r#"
+            [[users]]
+            enable = true
+            id = "a"
+            pwd = "a"
+            [users.proxy]
+            socks5 = "socks5://127.0.0.1:1080"
+
+            [[users]]
+            enable = true
+            id = "b"
+            pwd = "b"
+
+            [global]
+            retry = 1
+            [global.emailconf]
+            user = "user"
+            pwd = "pwd"
+            [global.proxy]
+            http = "http://127.0.0.1:8080"
+            [global.poster_proxy]
+            https = "http://127.0.0.1:8081"
+            user = "proxy_user"
+            pwd = "proxy_pwd"
+        "#;
+        let config: ConfigFile = toml::from_str(content).unwrap();
+        let g_conf = config.gloablconfig();
+        for mut u in config.get_users() {
+            u.update_proxy(g_conf);
+            let proxy = u.proxy().unwrap();
+            match u.id() {
+                "a" => assert_eq!(proxy.socks5.as_deref(), Some("socks5://127.0.0.1:1080")),
+                _ => assert_eq!(proxy.http.as_deref(), Some("http://127.0.0.1:8080")),
+            }
+            assert_eq!(u.poster_proxy(), g_conf.poster_proxy.as_ref());
+            assert_eq!(proxy.proxies().unwrap().len(), 1);
+        }
+        assert!(toml::to_string(&config).is_ok());
+    }
+
+    #[test]
+    fn template_test() {
+        let config: ConfigFile =
+            toml::from_str(include_str!("../../config_template.toml")).unwrap();
+        assert_eq!(config.http().timeout(), 5);
+        assert_eq!(config.http().ip_version(), None);
+        let g_conf = config.gloablconfig();
+        // 模版中的代理都是注释掉的示例
+        assert!(g_conf.proxy().is_none() && g_conf.poster_proxy().is_none());
+        assert_eq!(g_conf.max_concurrency(), 2);
+        assert_eq!(
+            g_conf.stagger(),
+            Some((Duration::from_secs(1), Duration::from_secs(5)))
+        );
+        assert_eq!(g_conf.scales(), &[100.0, 100.0]);
+        assert_eq!(g_conf.metric_kind(), MetricKind::Ensemble);
+        assert_eq!(g_conf.metric_weights().get(&MetricKind::Dssim), Some(&2.0));
+        assert_eq!(g_conf.fit(), Fit::Stretch);
+        assert_eq!(g_conf.poster_candidates(), 1);
+        for mut u in config.get_users() {
+            u.update_solver(g_conf);
+            assert_eq!(u.threshold(), 95.0);
+            assert_eq!(u.scales(), g_conf.scales());
         }
+        assert!(crate::http::client_builder(config.http(), None).is_ok());
+    }
+
+    #[test]
+    fn email_pwd_test() {
+        // 不发送邮件时，无法解析的邮件密码不影响读取配置
+        let template = include_str!("../../config_template.toml").replace(
+            "\npwd = \"pwd\"",
+            "\npwd = \"env:TJUPTATT_TEST_EMAIL_PWD_NOT_EXIST\"",
+        );
+        let mut config: ConfigFile = toml::from_str(&template).unwrap();
+        assert!(config.resolve_secrets().is_ok());
+        assert!(config.gloablconfig().emailconf().pwd().is_err());
     }
 }
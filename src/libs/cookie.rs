@@ -0,0 +1,98 @@
+//! 保存 tjupt 的 cookie
+//!
+//! 签到前读取，`TjuPtUser` 销毁时写回，登录过一次之后不需要每次都登录
+
+use crate::error::{Context, Error, Result};
+use reqwest_cookie_store::CookieStore;
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// 保存 cookie 的位置
+pub trait CookieStorage: Debug + Send + Sync {
+    /// 读取保存的 cookie，没有保存过则返回 `None`
+    fn load(&self) -> Result<Option<CookieStore>>;
+
+    /// 保存 cookie
+    fn save(&self, cookie: &CookieStore) -> Result<()>;
+}
+
+/// 保存为 json 文件，配置文件中的用户保存在状态文件夹中的 `{id}_cookie.json`
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl CookieStorage for FileStorage {
+    fn load(&self) -> Result<Option<CookieStore>> {
+        if !self.path.is_file() {
+            return Ok(None);
+        }
+        let file = File::open(&self.path).map(BufReader::new)?;
+        let cookie = CookieStore::load_json(file)
+            .map_err(|e| Error::parse(format!("无法读取cookie: {}", e)))?;
+        Ok(Some(cookie))
+    }
+
+    fn save(&self, cookie: &CookieStore) -> Result<()> {
+        let mut file = File::create(&self.path).context(format!(
+            "无法创建cookie文件: {}，请尝试 `--init`",
+            self.path.display()
+        ))?;
+        cookie
+            .save_json(&mut file)
+            .map_err(|e| Error::io(format!("无法写入cookie error: {}", e)))
+    }
+}
+
+/// 只保存在内存中，可以在多个 `TjuPtUser` 之间共享
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    json: Mutex<Option<Vec<u8>>>,
+}
+
+impl CookieStorage for MemoryStorage {
+    fn load(&self) -> Result<Option<CookieStore>> {
+        match *self.json.lock().expect("cookie 的锁已损坏") {
+            Some(ref json) => CookieStore::load_json(json.as_slice())
+                .map(Some)
+                .map_err(|e| Error::parse(format!("无法读取cookie: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, cookie: &CookieStore) -> Result<()> {
+        let mut json = vec![];
+        cookie
+            .save_json(&mut json)
+            .map_err(|e| Error::io(format!("无法写入cookie error: {}", e)))?;
+        *self.json.lock().expect("cookie 的锁已损坏") = Some(json);
+        Ok(())
+    }
+}
+
+impl<T: CookieStorage + ?Sized> CookieStorage for std::sync::Arc<T> {
+    fn load(&self) -> Result<Option<CookieStore>> {
+        (**self).load()
+    }
+
+    fn save(&self, cookie: &CookieStore) -> Result<()> {
+        (**self).save(cookie)
+    }
+}
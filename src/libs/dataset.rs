@@ -0,0 +1,573 @@
+//! 签到数据集及答案计算的基准测试
+//!
+//! 每个样本是一个文件夹，包含题图 `captcha.*`、海报 `posters/*` 及 `case.json`，扩展名与图片的格式相同
+
+use crate::{
+    error::{Context, Error, Result},
+    picparser::{self, Answer, Fit, Kaptcha, PosterClient},
+    similarity::SimilarityMetric,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    fs::{create_dir, create_dir_all, read, read_dir, read_to_string, write},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+
+const CASE_FILE: &str = "case.json";
+/// 之前的版本题图总是保存为 jpg，`case.json` 中没有记录
+const LEGACY_CAPTCHA_FILE: &str = "captcha.jpg";
+const POSTER_DIR: &str = "posters";
+
+/// 一个样本
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Case {
+    pub user: String,
+    /// 保存的时间，RFC3339
+    pub time: String,
+    /// 题图文件，相对于样本文件夹
+    #[serde(default = "legacy_captcha")]
+    pub captcha: String,
+    pub options: Vec<CaseOption>,
+    /// 选择的答案的值，没有选择则是 `None`
+    pub answer: Option<String>,
+    /// 提交后是否正确，没有提交或者无法确定则是 `None`
+    pub verified: Option<bool>,
+}
+
+/// 样本中的一个选项
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CaseOption {
+    pub name: String,
+    pub value: String,
+    /// 当时计算的相似度
+    pub score: Option<f64>,
+    /// 海报文件，相对于样本文件夹
+    pub poster: Option<String>,
+}
+
+fn legacy_captcha() -> String {
+    LEGACY_CAPTCHA_FILE.into()
+}
+
+impl Case {
+    /// 已知的正确答案
+    pub fn correct(&self) -> Option<&str> {
+        match self.verified {
+            Some(true) => self.answer.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// 已知的错误答案
+    pub fn wrong(&self) -> Option<&str> {
+        match self.verified {
+            Some(false) => self.answer.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// 以时间及用户命名的文件夹名，比如 `20230320T081500_alice`
+fn case_name(user: &str, now: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}_{}",
+        now.year(),
+        now.month() as u8,
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        user
+    )
+}
+
+/// 在 `dir` 中创建以时间及用户命名的文件夹，同一秒内重名时加上 `-1`、`-2` 等后缀
+///
+/// 数据集及调试数据共用，不会覆盖之前的文件夹
+pub fn create_case_dir(dir: &Path, user: &str, now: OffsetDateTime) -> Result<PathBuf> {
+    create_dir_all(dir).context(format!("无法创建文件夹: {}", dir.display()))?;
+    let name = case_name(user, now);
+    for n in 0.. {
+        let case_dir = match n {
+            0 => dir.join(&name),
+            n => dir.join(format!("{}-{}", name, n)),
+        };
+        match create_dir(&case_dir) {
+            Ok(()) => return Ok(case_dir),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => {
+                return Err(e).context(format!("无法创建文件夹: {}", case_dir.display()));
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// 数据集文件夹
+#[derive(Debug, Clone)]
+pub struct Dataset {
+    dir: PathBuf,
+}
+
+impl Dataset {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// 保存一个样本，返回样本文件夹
+    pub fn save(
+        &self,
+        user: &str,
+        kaptcha: &Kaptcha,
+        answers: &[Answer],
+        answer: Option<&str>,
+        verified: Option<bool>,
+    ) -> Result<PathBuf> {
+        let Some(ref img) = kaptcha.img_bytes else {
+            return Err(Error::captcha("没有题图"));
+        };
+        let now = OffsetDateTime::now_utc();
+        let case_dir = create_case_dir(&self.dir, user, now)?;
+        create_dir_all(case_dir.join(POSTER_DIR))
+            .context(format!("无法创建样本文件夹: {}", case_dir.display()))?;
+
+        let captcha = format!("captcha.{}", picparser::extension(img));
+        write(case_dir.join(&captcha), img)?;
+
+        let mut options = vec![];
+        for (n, a) in answers.iter().enumerate() {
+            let poster = match a.img_bytes {
+                Some(ref img) => {
+                    let poster = format!("{}/{}.{}", POSTER_DIR, n, picparser::extension(img));
+                    write(case_dir.join(&poster), img)?;
+                    Some(poster)
+                }
+                None => None,
+            };
+            options.push(CaseOption {
+                name: a.name.clone(),
+                value: a.value.clone(),
+                score: a.score,
+                poster,
+            });
+        }
+
+        let case = Case {
+            user: user.into(),
+            time: now
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            captcha,
+            options,
+            answer: answer.map(String::from),
+            verified,
+        };
+        write(
+            case_dir.join(CASE_FILE),
+            serde_json::to_string_pretty(&case)?,
+        )?;
+        Ok(case_dir)
+    }
+
+    /// 所有样本文件夹，按名称排序
+    pub fn cases(&self) -> Result<Vec<PathBuf>> {
+        let mut cases = read_dir(&self.dir)
+            .context(format!("无法读取数据集: {}", self.dir.display()))?
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.join(CASE_FILE).is_file())
+            .collect::<Vec<_>>();
+        cases.sort();
+        Ok(cases)
+    }
+}
+
+/// 读取样本，返回题图及有海报的选项
+pub fn load_case(case_dir: &Path) -> Result<(Case, Kaptcha, Vec<Answer>)> {
+    let case: Case = serde_json::from_str(&read_to_string(case_dir.join(CASE_FILE))?)
+        .context(format!("无法解析样本: {}", case_dir.display()))?;
+    let kaptcha = Kaptcha::with_img(
+        case_dir.display().to_string(),
+        read(case_dir.join(&case.captcha))?.into(),
+    )?;
+
+    let mut answers = vec![];
+    for o in case.options.iter() {
+        let Some(ref poster) = o.poster else {
+            continue;
+        };
+        let mut a = Answer::new(o.name.clone(), o.value.clone());
+        a.set_img(read(case_dir.join(poster))?.into())?;
+        answers.push(a);
+    }
+    Ok((case, kaptcha, answers))
+}
+
+/// 一个样本的基准测试结果
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub case: Case,
+    /// 选择的答案的值
+    pub pick: Option<String>,
+    pub elapsed: Duration,
+    /// 计算相似度出错时的错误信息，此时没有选择答案
+    pub error: Option<String>,
+}
+
+impl CaseResult {
+    /// 选择了已知的正确答案
+    pub fn is_correct(&self) -> bool {
+        self.pick.is_some() && self.pick.as_deref() == self.case.correct()
+    }
+
+    /// 选择了错误的答案
+    pub fn is_false_positive(&self) -> bool {
+        let Some(ref pick) = self.pick else {
+            return false;
+        };
+        match (self.case.correct(), self.case.wrong()) {
+            (Some(correct), _) => pick != correct,
+            (None, Some(wrong)) => pick == wrong,
+            (None, None) => false,
+        }
+    }
+
+    /// 是否知道答案的对错
+    pub fn is_labelled(&self) -> bool {
+        self.case.verified.is_some() && self.case.answer.is_some()
+    }
+}
+
+/// 基准测试报告
+#[derive(Debug, Default)]
+pub struct BenchReport {
+    pub results: Vec<CaseResult>,
+}
+
+impl BenchReport {
+    /// 准确率，已知正确答案的样本中选对的比例
+    pub fn accuracy(&self) -> Option<f64> {
+        let known = self
+            .results
+            .iter()
+            .filter(|r| r.case.correct().is_some())
+            .count();
+        let correct = self.results.iter().filter(|r| r.is_correct()).count();
+        ratio(correct, known)
+    }
+
+    /// 误选率，已知对错的样本中选错的比例
+    pub fn false_positive_rate(&self) -> Option<f64> {
+        let labelled = self.results.iter().filter(|r| r.is_labelled()).count();
+        let fp = self
+            .results
+            .iter()
+            .filter(|r| r.is_false_positive())
+            .count();
+        ratio(fp, labelled)
+    }
+
+    /// 平均耗时
+    pub fn mean_latency(&self) -> Duration {
+        match self.results.len() {
+            0 => Duration::ZERO,
+            n => self.results.iter().map(|r| r.elapsed).sum::<Duration>() / n as u32,
+        }
+    }
+
+    /// 最大耗时
+    pub fn max_latency(&self) -> Duration {
+        self.results
+            .iter()
+            .map(|r| r.elapsed)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+fn ratio(a: usize, b: usize) -> Option<f64> {
+    (b > 0).then(|| a as f64 / b as f64 * 100.0)
+}
+
+impl Display for BenchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |r: Option<f64>| match r {
+            Some(r) => format!("{:.2}%", r),
+            None => "-".into(),
+        };
+        let count = |p: fn(&CaseResult) -> bool| self.results.iter().filter(|r| p(r)).count();
+        writeln!(
+            f,
+            "样本: {} (已知正确答案: {}, 已知错误答案: {})",
+            self.results.len(),
+            count(|r| r.case.correct().is_some()),
+            count(|r| r.case.wrong().is_some()),
+        )?;
+        writeln!(f, "准确率: {}", percent(self.accuracy()))?;
+        writeln!(f, "误选率: {}", percent(self.false_positive_rate()))?;
+        writeln!(f, "未选择: {}", count(|r| r.pick.is_none()))?;
+        writeln!(f, "出错: {}", count(|r| r.error.is_some()))?;
+        write!(
+            f,
+            "耗时: 平均 {} ms, 最大 {} ms",
+            self.mean_latency().as_millis(),
+            self.max_latency().as_millis()
+        )
+    }
+}
+
+/// 使用 `threshold` 及 `metric` 重新计算数据集中的所有样本
+///
+/// 只使用保存的海报，不会访问网络
+pub async fn bench(
+    dataset: &Dataset,
+    threshold: f64,
+    metric: Arc<dyn SimilarityMetric>,
+    fit: Fit,
+) -> Result<BenchReport> {
+    // 所有海报都已经在本地，不会使用
+    let poster_client = PosterClient::new(reqwest::Client::new());
+    let mut report = BenchReport::default();
+    for case_dir in dataset.cases()? {
+        let (case, mut kaptcha, mut answers) = match load_case(&case_dir) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("跳过样本: {}, Err: {}", case_dir.display(), e.report());
+                continue;
+            }
+        };
+        kaptcha.set_metric(metric.clone());
+        kaptcha.set_fit(fit);
+
+        let start = Instant::now();
+        let scored = kaptcha
+            .score_answers(&mut answers, poster_client.transport(), &poster_client)
+            .await;
+        let (pick, error) = match scored {
+            Ok(()) => (
+                picparser::pick_answer(&answers, threshold).map(|a| a.value.clone()),
+                None,
+            ),
+            Err(e) => {
+                log::warn!("样本出错: {}, Err: {}", case_dir.display(), e.report());
+                (None, Some(e.to_string()))
+            }
+        };
+        let result = CaseResult {
+            case,
+            pick,
+            elapsed: start.elapsed(),
+            error,
+        };
+        if result.is_false_positive() {
+            log::info!("选错了: {}", case_dir.display());
+        }
+        report.results.push(result);
+    }
+    Ok(report)
+}
+
+/// 相似度阈值的建议
+#[derive(Debug, Default)]
+pub struct Calibration {
+    /// 正确选项的相似度
+    pub positives: Vec<f64>,
+    /// 错误选项的相似度
+    pub negatives: Vec<f64>,
+    /// 推荐的阈值及此时的误选、漏选数量，没有正确选项时是 `None`
+    pub recommended: Option<(f64, usize, usize)>,
+    /// 计算相似度出错而跳过的样本数
+    pub errors: usize,
+}
+
+impl Display for Calibration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let range = |v: &[f64]| {
+            let min = v.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = v.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            if v.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{:.2}% ~ {:.2}%", min, max)
+            }
+        };
+        writeln!(
+            f,
+            "正确选项: {} ({})",
+            self.positives.len(),
+            range(&self.positives)
+        )?;
+        writeln!(
+            f,
+            "错误选项: {} ({})",
+            self.negatives.len(),
+            range(&self.negatives)
+        )?;
+        if self.errors > 0 {
+            writeln!(f, "出错跳过的样本: {}", self.errors)?;
+        }
+        match self.recommended {
+            Some((threshold, fp, fn_)) => write!(
+                f,
+                "推荐阈值: {:.2}%，错误选项高于阈值: {}，正确选项低于阈值: {}",
+                threshold, fp, fn_
+            ),
+            None => write!(f, "没有已知正确答案的样本，无法推荐阈值"),
+        }
+    }
+}
+
+/// 推荐阈值，使高于阈值的错误选项与低于阈值的正确选项最少，相同时错误选项更少的优先
+///
+/// 阈值取在相邻的两个相似度中间
+pub fn recommend(positives: &[f64], negatives: &[f64]) -> Option<(f64, usize, usize)> {
+    let errors = |t: f64| {
+        let fp = negatives.iter().filter(|&&s| s >= t).count();
+        let fn_ = positives.iter().filter(|&&s| s < t).count();
+        (fp, fn_)
+    };
+    let best = positives.iter().copied().min_by(|&a, &b| {
+        let (fp_a, fn_a) = errors(a);
+        let (fp_b, fn_b) = errors(b);
+        (fp_a + fn_a, fp_a)
+            .cmp(&(fp_b + fn_b, fp_b))
+            .then(b.total_cmp(&a))
+    })?;
+    let below = positives
+        .iter()
+        .chain(negatives.iter())
+        .copied()
+        .filter(|&s| s < best)
+        .fold(None, |acc: Option<f64>, s| {
+            Some(acc.map_or(s, |m| m.max(s)))
+        });
+    let threshold = match below {
+        Some(below) => (best + below) / 2.0,
+        None => best,
+    };
+    let (fp, fn_) = errors(threshold);
+    Some((threshold, fp, fn_))
+}
+
+/// 使用 `metric` 重新计算数据集中已知对错的样本，推荐阈值
+///
+/// 答案正确的样本中，提交的是正确选项，其他是错误选项；答案错误的样本中只有提交的是错误选项
+pub async fn calibrate(
+    dataset: &Dataset,
+    metric: Arc<dyn SimilarityMetric>,
+    fit: Fit,
+) -> Result<Calibration> {
+    let poster_client = PosterClient::new(reqwest::Client::new());
+    let mut calibration = Calibration::default();
+    for case_dir in dataset.cases()? {
+        let (case, mut kaptcha, mut answers) = match load_case(&case_dir) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("跳过样本: {}, Err: {}", case_dir.display(), e.report());
+                continue;
+            }
+        };
+        if case.verified.is_none() || case.answer.is_none() {
+            continue;
+        }
+        kaptcha.set_metric(metric.clone());
+        kaptcha.set_fit(fit);
+        if let Err(e) = kaptcha
+            .score_answers(&mut answers, poster_client.transport(), &poster_client)
+            .await
+        {
+            log::warn!("样本出错: {}, Err: {}", case_dir.display(), e.report());
+            calibration.errors += 1;
+            continue;
+        }
+        for a in answers.iter() {
+            let Some(score) = a.score else {
+                continue;
+            };
+            if case.correct() == Some(a.value.as_str()) {
+                calibration.positives.push(score);
+            } else if case.correct().is_some() || case.wrong() == Some(a.value.as_str()) {
+                calibration.negatives.push(score);
+            }
+        }
+    }
+    calibration.recommended = recommend(&calibration.positives, &calibration.negatives);
+    Ok(calibration)
+}
+
+#[cfg(test)]
+mod dataset_test {
+    use super::*;
+
+    fn result(answer: &str, verified: Option<bool>, pick: Option<&str>) -> CaseResult {
+        CaseResult {
+            case: Case {
+                user: "user".into(),
+                time: String::new(),
+                captcha: legacy_captcha(),
+                options: vec![],
+                answer: Some(answer.into()),
+                verified,
+            },
+            pick: pick.map(String::from),
+            elapsed: Duration::from_millis(10),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn report_test() {
+        let report = BenchReport {
+            results: vec![
+                result("1", Some(true), Some("1")),
+                result("1", Some(true), Some("2")),
+                result("1", Some(true), None),
+                result("3", Some(false), Some("3")),
+                result("3", Some(false), Some("4")),
+                result("3", None, Some("3")),
+            ],
+        };
+        assert_eq!(report.accuracy(), Some(1.0 / 3.0 * 100.0));
+        assert_eq!(report.false_positive_rate(), Some(40.0));
+        assert_eq!(report.mean_latency(), Duration::from_millis(10));
+        assert!(BenchReport::default().accuracy().is_none());
+    }
+
+    #[test]
+    fn case_dir_test() {
+        let dir = std::env::temp_dir().join(format!("tjuptatt_case_dir_{}", std::process::id()));
+        let now = OffsetDateTime::UNIX_EPOCH;
+        // 同一秒内的样本不会互相覆盖
+        let first = create_case_dir(&dir, "alice", now).unwrap();
+        let second = create_case_dir(&dir, "alice", now).unwrap();
+        assert_eq!(first, dir.join("19700101T000000_alice"));
+        assert_eq!(second, dir.join("19700101T000000_alice-1"));
+
+        // 之前的版本没有记录题图文件
+        let case: Case = serde_json::from_str(
+            r#"{"user":"alice","time":"","options":[],"answer":null,"verified":null}"#,
+        )
+        .unwrap();
+        assert_eq!(case.captcha, "captcha.jpg");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recommend_test() {
+        // 可以完全区分
+        assert_eq!(recommend(&[95.0, 97.0], &[80.0, 90.0]), Some((92.5, 0, 0)));
+        // 有重叠时错误最少
+        assert_eq!(
+            recommend(&[90.0, 96.0, 98.0], &[85.0, 94.0]),
+            Some((95.0, 0, 1))
+        );
+        assert_eq!(recommend(&[], &[80.0]), None);
+    }
+}
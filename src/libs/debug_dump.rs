@@ -0,0 +1,174 @@
+//! 签到失败时保存调试数据
+//!
+//! 每次失败保存在一个以时间及用户命名的文件夹中，包含 `trace.json`、签到页面、登录的响应、题图及海报，
+//! 可以直接附在 bug 报告中。密码及 passkey 会被替换为 `***`
+
+use crate::{
+    dataset,
+    error::{Context, Error, Result},
+    picparser::{self, Answer, Kaptcha},
+    secret::redact,
+};
+use bytes::Bytes;
+use serde::Serialize;
+use std::{
+    fs::{create_dir_all, write},
+    path::{Path, PathBuf},
+};
+use time::OffsetDateTime;
+
+const TRACE_FILE: &str = "trace.json";
+const POSTER_DIR: &str = "posters";
+
+/// 一次签到过程中收集的数据
+#[derive(Debug, Default)]
+pub struct Trace {
+    responses: Vec<TraceResponse>,
+    captcha: Option<Bytes>,
+    captcha_url: Option<String>,
+    options: Vec<(TraceOption, Option<Bytes>)>,
+    answer: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct TraceResponse {
+    step: String,
+    url: String,
+    status: u16,
+    /// 保存的文件，相对于调试数据文件夹
+    file: String,
+    #[serde(skip)]
+    body: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct TraceOption {
+    name: String,
+    value: String,
+    img_url: Option<String>,
+    score: Option<f64>,
+    error: Option<String>,
+    /// 海报文件，相对于调试数据文件夹
+    poster: Option<String>,
+}
+
+/// `trace.json` 的内容
+#[derive(Serialize, Debug)]
+struct TraceFile<'a> {
+    user: &'a str,
+    /// RFC3339
+    time: String,
+    error: String,
+    responses: &'a [TraceResponse],
+    captcha: Option<String>,
+    captcha_url: Option<&'a str>,
+    options: Vec<TraceOption>,
+    answer: Option<&'a str>,
+}
+
+impl Trace {
+    /// 记录一个响应，`step` 为请求的名字，比如 `login`
+    pub fn response(&mut self, step: &str, url: &str, status: u16, body: &str) {
+        let file = format!("{}_{}.html", self.responses.len(), step);
+        self.responses.push(TraceResponse {
+            step: step.into(),
+            url: url.into(),
+            status,
+            file,
+            body: body.into(),
+        });
+    }
+
+    /// 记录题图、选项及提交的答案
+    pub fn kaptcha(&mut self, kaptcha: &Kaptcha, answers: &[Answer], answer: Option<&str>) {
+        self.captcha = kaptcha.img_bytes.clone();
+        self.captcha_url = Some(kaptcha.url.clone());
+        self.options = answers
+            .iter()
+            .map(|a| {
+                let option = TraceOption {
+                    name: a.name.clone(),
+                    value: a.value.clone(),
+                    img_url: a.img_url.clone(),
+                    score: a.score,
+                    error: a.error.clone(),
+                    poster: None,
+                };
+                (option, a.img_bytes.clone())
+            })
+            .collect();
+        self.answer = answer.map(str::to_owned);
+    }
+}
+
+/// 保存调试数据的文件夹
+#[derive(Debug, Clone)]
+pub struct DebugDump {
+    dir: PathBuf,
+}
+
+impl DebugDump {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// 保存一次失败的签到，返回保存的文件夹
+    ///
+    /// `secrets` 中的字符串会从响应中去掉
+    pub fn save(
+        &self,
+        user: &str,
+        secrets: &[&str],
+        trace: &Trace,
+        err: &Error,
+    ) -> Result<PathBuf> {
+        let now = OffsetDateTime::now_utc();
+        let dump_dir = dataset::create_case_dir(&self.dir, user, now)?;
+        create_dir_all(dump_dir.join(POSTER_DIR))
+            .context(format!("无法创建调试数据文件夹: {}", dump_dir.display()))?;
+
+        for r in trace.responses.iter() {
+            write(dump_dir.join(&r.file), redact(&r.body, secrets))?;
+        }
+
+        let captcha = match trace.captcha {
+            Some(ref img) => {
+                let file = format!("captcha.{}", picparser::extension(img));
+                write(dump_dir.join(&file), img)?;
+                Some(file)
+            }
+            None => None,
+        };
+
+        let mut options = vec![];
+        for (n, (option, img)) in trace.options.iter().enumerate() {
+            let mut option = option.clone();
+            if let Some(img) = img {
+                let poster = format!("{}/{}.{}", POSTER_DIR, n, picparser::extension(img));
+                write(dump_dir.join(&poster), img)?;
+                option.poster = Some(poster);
+            }
+            options.push(option);
+        }
+
+        let trace_file = TraceFile {
+            user,
+            time: now
+                .format(&time::format_description::well_known::Rfc3339)
+                .unwrap_or_default(),
+            error: redact(&err.report().to_string(), secrets),
+            responses: &trace.responses,
+            captcha,
+            captcha_url: trace.captcha_url.as_deref(),
+            options,
+            answer: trace.answer.as_deref(),
+        };
+        write(
+            dump_dir.join(TRACE_FILE),
+            serde_json::to_string_pretty(&trace_file)?,
+        )?;
+        Ok(dump_dir)
+    }
+}
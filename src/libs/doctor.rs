@@ -0,0 +1,342 @@
+//! 环境诊断
+//!
+//! 检查文件夹、配置文件、网络、cookie 及邮件设置，输出检查报告
+
+use crate::{
+    command::DIRS,
+    config::{ConfigFile, GlobalConfig, HttpConfig, ProxyConfig},
+    cookie::{CookieStorage, FileStorage},
+    email_bot,
+    error::{Context, Error, Result},
+    http::client_builder,
+};
+use reqwest_cookie_store::CookieStoreMutex;
+use std::{
+    fmt::Display,
+    fs::{remove_file, File},
+    path::Path,
+    sync::Arc,
+};
+
+/// 检查结果的等级
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pass => write!(f, "PASS"),
+            Self::Warn => write!(f, "WARN"),
+            Self::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+/// 单项检查结果
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+/// 检查报告
+#[derive(Debug, Default)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    /// 是否有失败的检查
+    pub fn has_failure(&self) -> bool {
+        self.checks.iter().any(|c| c.status == CheckStatus::Fail)
+    }
+
+    fn push<N, D>(&mut self, name: N, status: CheckStatus, detail: D)
+    where
+        N: Into<String>,
+        D: Display,
+    {
+        self.checks.push(Check {
+            name: name.into(),
+            status,
+            detail: detail.to_string(),
+        });
+    }
+
+    fn pass<N: Into<String>, D: Display>(&mut self, name: N, detail: D) {
+        self.push(name, CheckStatus::Pass, detail);
+    }
+
+    fn warn<N: Into<String>, D: Display>(&mut self, name: N, detail: D) {
+        self.push(name, CheckStatus::Warn, detail);
+    }
+
+    fn fail<N: Into<String>, D: Display>(&mut self, name: N, detail: D) {
+        self.push(name, CheckStatus::Fail, detail);
+    }
+
+    /// 记录结果，`Err` 视为失败
+    fn result<N: Into<String>, D: Display>(&mut self, name: N, res: Result<D>) {
+        match res {
+            Ok(d) => self.pass(name, d),
+            Err(e) => self.fail(name, e.report().to_string()),
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for c in self.checks.iter() {
+            writeln!(f, "[{}] {}: {}", c.status, c.name, c.detail)?;
+        }
+        let count = |s| self.checks.iter().filter(|c| c.status == s).count();
+        write!(
+            f,
+            "通过: {}, 警告: {}, 失败: {}",
+            count(CheckStatus::Pass),
+            count(CheckStatus::Warn),
+            count(CheckStatus::Fail)
+        )
+    }
+}
+
+/// 运行所有检查
+pub async fn diagnose(config_path: &Path) -> Report {
+    let mut report = Report::default();
+
+    // 文件夹
+    for (name, dir) in [
+        ("配置文件夹", DIRS.config_dir()),
+        ("状态文件夹", DIRS.state_dir()),
+    ] {
+        check_dir(&mut report, name, dir);
+    }
+
+    // 配置文件
+    if !config_path.is_file() {
+        report.fail(
+            "配置文件",
+            format!("不存在: {}，请先 `--init`", config_path.display()),
+        );
+        return report;
+    }
+    check_permissions(&mut report, "配置文件权限", config_path);
+
+    if let Err(e) = ConfigFile::new_from_unresolved(config_path) {
+        report.fail("配置文件解析", e.report().to_string());
+        return report;
+    }
+    report.pass("配置文件解析", config_path.display());
+
+    let config = match ConfigFile::new_from(config_path) {
+        Ok(config) => {
+            report.pass("密码解析", "所有开启的用户及邮件配置的密码均可解析");
+            config
+        }
+        Err(e) => {
+            report.fail("密码解析", e.report().to_string());
+            return report;
+        }
+    };
+
+    // 网络
+    let g_conf = config.gloablconfig();
+    report.result(
+        "tjupt 连接",
+        check_url(config.http(), g_conf, false, &g_conf.site_urls().login()).await,
+    );
+    report.result(
+        "豆瓣连接",
+        check_url(config.http(), g_conf, true, g_conf.suggest_url()).await,
+    );
+
+    // cookie
+    for mut u in config.get_users().into_iter().filter(|u| u.enable()) {
+        u.update_proxy(g_conf);
+        let name = format!("cookie: {}", u.id());
+        let cookie_path = DIRS.state_dir().join(format!("{}_cookie.json", u.id()));
+        if !cookie_path.is_file() {
+            report.warn(name, "没有保存的cookie，签到时会重新登陆");
+            continue;
+        }
+        check_permissions(
+            &mut report,
+            format!("cookie 权限: {}", u.id()),
+            &cookie_path,
+        );
+        let storage = FileStorage::new(&cookie_path);
+        let attendance = g_conf.site_urls().attendance();
+        match check_cookie(config.http(), u.proxy(), &attendance, &storage).await {
+            Ok(true) => report.pass(name, "有效"),
+            Ok(false) => report.warn(name, "已失效，签到时会重新登陆"),
+            Err(e) => report.fail(name, e.report().to_string()),
+        }
+    }
+
+    // 邮件
+    let email_config = g_conf.emailconf();
+    let res = {
+        let email_config = email_config.clone();
+        tokio::task::spawn_blocking(move || email_bot::test_login(&email_config))
+            .await
+            .map_err(|e| Error::notification("无法测试邮件登陆").with_source(e))
+            .and_then(|res| res)
+    };
+    match res {
+        Ok(()) => report.pass("邮件登陆", email_config),
+        Err(e) => report.warn(
+            "邮件登陆",
+            format!("{}，只影响 `--email` 的邮件提醒", e.report()),
+        ),
+    }
+
+    report
+}
+
+/// 检查文件夹存在且可写
+fn check_dir(report: &mut Report, name: &str, dir: &Path) {
+    if !dir.is_dir() {
+        report.fail(name, format!("不存在: {}，请先 `--init`", dir.display()));
+        return;
+    }
+    let probe = dir.join(".tjuptatt_doctor");
+    match File::create(&probe) {
+        Ok(_) => {
+            let _r = remove_file(&probe);
+            report.pass(name, dir.display());
+        }
+        Err(e) => report.fail(name, format!("无法写入: {}, {}", dir.display(), e)),
+    }
+}
+
+/// 检查文件是否只有自己可以读写
+#[cfg(unix)]
+fn check_permissions<N: Into<String>>(report: &mut Report, name: N, path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+
+    match path.metadata() {
+        Ok(meta) => {
+            let mode = meta.permissions().mode() & 0o777;
+            if mode & 0o077 == 0 {
+                report.pass(name, format!("{:o}", mode));
+            } else {
+                report.warn(
+                    name,
+                    format!(
+                        "{:o}，其他用户可以读取，建议 `chmod 600 {}`",
+                        mode,
+                        path.display()
+                    ),
+                );
+            }
+        }
+        Err(e) => report.fail(name, e),
+    }
+}
+
+#[cfg(not(unix))]
+fn check_permissions<N: Into<String>>(_report: &mut Report, _name: N, _path: &Path) {}
+
+/// 检查保存的 cookie 能否访问签到页面
+///
+/// 只读取 `storage`，不会写回
+async fn check_cookie(
+    http: &HttpConfig,
+    proxy: Option<&ProxyConfig>,
+    attendance: &str,
+    storage: &dyn CookieStorage,
+) -> Result<bool> {
+    let Some(cookie) = storage.load()? else {
+        return Err(Error::io("没有保存的cookie"));
+    };
+    let client = client_builder(http, proxy)?
+        .cookie_provider(Arc::new(CookieStoreMutex::new(cookie)))
+        .build()
+        .context_as(Error::config, "无法创建 http client")?;
+    let res = client.get(attendance).send().await?;
+    Ok(!res.url().as_str().contains("login.php"))
+}
+
+/// 检查能否访问
+async fn check_url(
+    http: &HttpConfig,
+    g_conf: &GlobalConfig,
+    poster: bool,
+    url: &str,
+) -> Result<String> {
+    let proxy = if poster {
+        g_conf.poster_proxy()
+    } else {
+        g_conf.proxy()
+    };
+    let client = client_builder(http, proxy)?.build()?;
+    let res = client.get(url).send().await?;
+    Ok(format!("{} {}", url, res.status()))
+}
+
+#[cfg(test)]
+mod doctor_test {
+    use super::*;
+    #[cfg(feature = "mock")]
+    use crate::{
+        bot::TjuPtUser,
+        command::SiteUrls,
+        cookie::MemoryStorage,
+        mock::{MockServer, Scenario, MOCK_PWD, MOCK_USER},
+    };
+
+    #[cfg(feature = "mock")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn check_cookie_test() {
+        let server = MockServer::start(Scenario::Success).await.unwrap();
+        let attendance = SiteUrls::new(&server.url()).attendance();
+        let http = HttpConfig::default();
+        let storage = Arc::new(MemoryStorage::default());
+        assert!(check_cookie(&http, None, &attendance, &storage)
+            .await
+            .is_err());
+
+        // 登陆一次，销毁时保存 cookie
+        let user = TjuPtUser::builder(MOCK_USER, MOCK_PWD)
+            .site_url(&server.url())
+            .suggest_url(&server.suggest_url())
+            .cookie_storage(storage.clone())
+            .build()
+            .unwrap();
+        user.dry_run().await.unwrap();
+        drop(user);
+
+        let path =
+            std::env::temp_dir().join(format!("tjuptatt_doctor_{}.json", std::process::id()));
+        let file = FileStorage::new(&path);
+        file.save(&storage.load().unwrap().unwrap()).unwrap();
+        let before = std::fs::read(&path).unwrap();
+        assert!(check_cookie(&http, None, &attendance, &file).await.unwrap());
+        // 检查不会修改保存的 cookie
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn report_test() {
+        let mut report = Report::default();
+        report.pass("a", "ok");
+        report.warn("b", "slow");
+        assert!(!report.has_failure());
+        report.result::<_, String>("c", Err(Error::io("broken")));
+        assert!(report.has_failure());
+        assert_eq!(
+            report.to_string(),
+            "[PASS] a: ok\n[WARN] b: slow\n[FAIL] c: broken\n通过: 1, 警告: 1, 失败: 1"
+        );
+    }
+}
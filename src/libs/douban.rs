@@ -0,0 +1,302 @@
+//! 从豆瓣搜索选项对应的电影
+//!
+//! 选项名可能带有年份、全角字符或原名，先规范化，再与搜索建议的 `title` 及 `sub_title` 比较
+
+use crate::error::{Error, Result};
+use crate::picparser::PosterClient;
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// 默认比较前几个搜索结果的海报，与之前的版本相同只取第一个，不增加访问豆瓣的次数
+pub const DEFAULT_CANDIDATES: usize = 1;
+
+/// 年份相同时额外加的分数，用来区分同名的电影
+const YEAR_BONUS: f64 = 0.2;
+
+/// 豆瓣搜索建议中的一项
+#[derive(Deserialize, Debug, Clone)]
+pub struct DouBanData {
+    /// 图片链接
+    pub img: String,
+    pub title: String,
+    /// 一般是原名
+    pub sub_title: Option<String>,
+    pub year: Option<String>,
+}
+
+impl Display for DouBanData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.sub_title {
+            None => write!(f, "DoubanData[{}]", self.title),
+            Some(subtitle) => write!(f, "DoubanData[{}-{}]", self.title, subtitle),
+        }
+    }
+}
+
+/// 搜索 `name`，按与 `name` 的匹配程度排序，最多返回 `limit` 个
+///
+/// 只用规范化后的中文名搜索，匹配程度相同的保持豆瓣给出的顺序，都不匹配时返回豆瓣的前几个
+pub async fn search(name: &str, client: &PosterClient, limit: usize) -> Result<Vec<DouBanData>> {
+    let res: Vec<DouBanData> = client
+        .get(client.suggest_url(), &[("q", search_term(name))])
+        .await?
+        .json()
+        .await?;
+    if res.is_empty() {
+        return Err(Error::parse("无法获取豆瓣数据"));
+    }
+    Ok(rank(name, res, limit))
+}
+
+/// 搜索使用的名字
+///
+/// 转换全角字符，取 `/` 或 `|` 之前的部分，去掉年份及原名，比如 `流浪地球 The Wandering Earth (2019)` 搜索 `流浪地球`
+fn search_term(name: &str) -> String {
+    let name = half_width(name);
+    let primary = name
+        .split(['/', '|'])
+        .find(|p| !normalize_title(p).is_empty())
+        .unwrap_or(&name);
+    let primary = take_year(primary).map_or_else(|| primary.to_owned(), |(rest, _)| rest);
+    let primary = match split_original(&primary) {
+        Some([cn, _]) => cn,
+        None => primary,
+    };
+    let term = primary.split_whitespace().collect::<Vec<_>>().join(" ");
+    let term = term.trim_matches(|c: char| !c.is_alphanumeric());
+    if term.is_empty() {
+        name.trim().to_owned()
+    } else {
+        term.to_owned()
+    }
+}
+
+/// 按与 `name` 的匹配程度排序
+fn rank(name: &str, res: Vec<DouBanData>, limit: usize) -> Vec<DouBanData> {
+    let query = Query::new(name);
+    let mut scored = res
+        .into_iter()
+        .map(|d| (query.score(&d), d))
+        .collect::<Vec<_>>();
+    if scored.iter().any(|(s, _)| *s > 0.0) {
+        scored.retain(|(s, _)| *s > 0.0);
+    }
+    // 稳定排序
+    scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    scored
+        .into_iter()
+        .take(limit.max(1))
+        .map(|(_, d)| d)
+        .collect()
+}
+
+/// 规范化后的选项名
+#[derive(Debug, PartialEq)]
+struct Query {
+    /// 可能的名字，包括去掉年份的及拆分出的中文名、原名
+    titles: Vec<String>,
+    year: Option<u32>,
+}
+
+impl Query {
+    fn new(name: &str) -> Self {
+        let name = half_width(name);
+        let mut raw = vec![name.clone()];
+        raw.extend(name.split(['/', '|']).map(str::to_owned));
+        for part in raw.clone() {
+            raw.extend(split_original(&part).into_iter().flatten());
+        }
+
+        let mut year = None;
+        let mut titles = vec![];
+        for part in raw {
+            if let Some((rest, y)) = take_year(&part) {
+                year = year.or(Some(y));
+                titles.push(normalize_title(&rest));
+            }
+            titles.push(normalize_title(&part));
+        }
+        titles.retain(|t| !t.is_empty());
+        titles.sort();
+        titles.dedup();
+        Self { titles, year }
+    }
+
+    /// 与搜索结果的匹配程度，名字完全相同是 1，年份相同再加分
+    fn score(&self, data: &DouBanData) -> f64 {
+        let names = std::iter::once(&data.title)
+            .chain(data.sub_title.as_ref())
+            .map(|t| normalize_title(&half_width(t)))
+            .filter(|t| !t.is_empty())
+            .collect::<Vec<_>>();
+        let score = self
+            .titles
+            .iter()
+            .flat_map(|q| names.iter().map(move |n| title_similarity(q, n)))
+            .fold(0.0, f64::max);
+        let same_year = self.year.is_some()
+            && self.year == data.year.as_deref().and_then(|y| y.trim().parse().ok());
+        if score > 0.0 && same_year {
+            score + YEAR_BONUS
+        } else {
+            score
+        }
+    }
+}
+
+/// 全角字符转换为半角
+fn half_width(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// 只保留字母、数字及汉字，并转为小写
+fn normalize_title(s: &str) -> String {
+    s.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// 去掉名字中的年份，比如 `流浪地球 (2019)`
+///
+/// 年份前面不能是字母或数字，避免把 `银翼杀手2049` 中的数字当作年份，只剩年份时不去掉
+fn take_year(s: &str) -> Option<(String, u32)> {
+    let chars = s.char_indices().collect::<Vec<_>>();
+    let mut found = None;
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].1.is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && chars[i].1.is_ascii_digit() {
+            i += 1;
+        }
+        let separated = start == 0 || !chars[start - 1].1.is_alphanumeric();
+        if i - start == 4 && separated {
+            let begin = chars[start].0;
+            let end = chars.get(i).map(|c| c.0).unwrap_or(s.len());
+            if let Ok(year @ 1880..=2099) = s[begin..end].parse::<u32>() {
+                found = Some((begin, end, year));
+            }
+        }
+    }
+
+    let (begin, end, year) = found?;
+    let rest = format!("{}{}", &s[..begin], &s[end..]);
+    (!normalize_title(&rest).is_empty()).then_some((rest, year))
+}
+
+/// 拆分中文名及原名，比如 `流浪地球 The Wandering Earth`
+///
+/// 在最后一个汉字之后的空白处拆分，后面的部分不能包含汉字
+fn split_original(s: &str) -> Option<[String; 2]> {
+    let last_cjk = s.char_indices().rfind(|(_, c)| is_cjk(*c))?.0;
+    let (cn, rest) = s.split_at(last_cjk);
+    let cjk_len = rest.chars().next()?.len_utf8();
+    let (cn_tail, original) = rest.split_at(cjk_len);
+    if !original.starts_with(char::is_whitespace) || normalize_title(original).is_empty() {
+        return None;
+    }
+    Some([format!("{}{}", cn, cn_tail), original.trim().to_owned()])
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '\u{F900}'..='\u{FAFF}')
+}
+
+/// 两个规范化后的名字的相似度，相同是 1，否则是字符二元组的 Dice 系数
+fn title_similarity(a: &str, b: &str) -> f64 {
+    if a == b {
+        return 1.0;
+    }
+    let grams = |s: &str| {
+        let chars = s.chars().collect::<Vec<_>>();
+        if chars.len() < 2 {
+            return vec![chars.iter().collect::<String>()];
+        }
+        chars
+            .windows(2)
+            .map(|w| w.iter().collect::<String>())
+            .collect::<Vec<_>>()
+    };
+    let (a, mut b) = (grams(a), grams(b));
+    let total = a.len() + b.len();
+    let mut common = 0;
+    for g in a.iter() {
+        if let Some(pos) = b.iter().position(|x| x == g) {
+            b.swap_remove(pos);
+            common += 1;
+        }
+    }
+    // 不完全相同时最多 0.99
+    (2.0 * common as f64 / total as f64).min(0.99)
+}
+
+#[cfg(test)]
+mod douban_test {
+    use super::*;
+
+    fn data(title: &str, sub_title: &str, year: &str) -> DouBanData {
+        DouBanData {
+            img: format!("{}.jpg", title),
+            title: title.into(),
+            sub_title: Some(sub_title.into()),
+            year: Some(year.into()),
+        }
+    }
+
+    #[test]
+    fn query_test() {
+        let q = Query::new("流浪地球（２０１９）");
+        assert_eq!(q.year, Some(2019));
+        assert!(q.titles.contains(&"流浪地球".to_owned()));
+
+        let q = Query::new("流浪地球 The Wandering Earth");
+        assert!(q.titles.contains(&"流浪地球".to_owned()));
+        assert!(q.titles.contains(&"thewanderingearth".to_owned()));
+
+        let q = Query::new("银翼杀手2049");
+        assert_eq!(q.year, None);
+        assert_eq!(Query::new("1917").year, None);
+    }
+
+    #[test]
+    fn search_term_test() {
+        assert_eq!(search_term("流浪地球（２０１９）"), "流浪地球");
+        assert_eq!(
+            search_term("流浪地球 The Wandering Earth (2019)"),
+            "流浪地球"
+        );
+        assert_eq!(search_term("狮子王 / The Lion King (1994)"), "狮子王");
+        assert_eq!(search_term("  The  Lion King "), "The Lion King");
+        assert_eq!(search_term("银翼杀手2049"), "银翼杀手2049");
+        assert_eq!(search_term("1917"), "1917");
+    }
+
+    #[test]
+    fn rank_test() {
+        let res = vec![
+            data("狮子王", "The Lion King", "2019"),
+            data("狮子王2", "The Lion King II", "1998"),
+            data("狮子王", "The Lion King", "1994"),
+            data("无关", "Nothing", "1994"),
+        ];
+        let ranked = rank("狮子王 / The Lion King (1994)", res.clone(), 3);
+        assert_eq!(ranked.len(), 3);
+        assert_eq!(ranked[0].year.as_deref(), Some("1994"));
+        assert_eq!(ranked[1].year.as_deref(), Some("2019"));
+        assert!(ranked.iter().all(|d| d.title != "无关"));
+
+        // 都不匹配时保持原本的顺序
+        let ranked = rank("完全不同", res, 2);
+        assert_eq!(ranked[0].year.as_deref(), Some("2019"));
+    }
+}
@@ -4,9 +4,23 @@ use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
 use crate::config::EmailConfig;
-use anyhow::Result;
+use crate::error::{Error, Result};
 
-pub fn send_email(e_config: &EmailConfig, rece: &str, content: &str) -> Result<()> {
+/// 根据配置连接 smtp 服务器
+fn mailer(e_config: &EmailConfig) -> Result<SmtpTransport> {
+    let creds = Credentials::new(e_config.user().into(), e_config.pwd()?.expose().into());
+
+    let port = u16::try_from(e_config.port())
+        .map_err(|_| Error::config(format!("无效的端口: {}", e_config.port())))?;
+
+    Ok(SmtpTransport::relay(e_config.host())?
+        .port(port)
+        .credentials(creds)
+        .build())
+}
+
+/// 发送邮件，返回 smtp 服务器的响应
+pub fn send_email(e_config: &EmailConfig, rece: &str, content: &str) -> Result<String> {
     let f_email = format!("TJUPT_BOT <{}>", e_config.sender());
     let t_email = format!("YOU <{}>", rece);
 
@@ -16,19 +30,30 @@ pub fn send_email(e_config: &EmailConfig, rece: &str, content: &str) -> Result<(
         .subject("TJUPT BOT STATUS")
         .body(String::from(content))?;
 
-    let creds = Credentials::new(e_config.user().into(), e_config.pwd().into());
-
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(e_config.host())?
-        .credentials(creds)
-        .build();
+    let mailer = mailer(e_config)?;
 
     // Send the email
     match mailer.send(&email) {
-        Ok(_) => {
+        Ok(res) => {
             log::debug!("发送邮件成功: {}", t_email);
-            Ok(())
+            Ok(format!(
+                "{} {}",
+                res.code(),
+                res.message().collect::<Vec<_>>().join(" ")
+            ))
         }
         Err(e) => Err(e.into()),
     }
 }
+
+/// 测试能否登录 smtp 服务器，不发送邮件
+pub fn test_login(e_config: &EmailConfig) -> Result<()> {
+    if mailer(e_config)?.test_connection()? {
+        Ok(())
+    } else {
+        Err(Error::notification(format!(
+            "无法连接到: {}",
+            e_config.host()
+        )))
+    }
+}
@@ -0,0 +1,339 @@
+//! 错误类型
+//!
+//! 库中的函数都返回 [`Result`]，可以根据 [`Error::kind`] 分别处理。
+//! 每个错误带有说明及来源，[`Error::report`] 依次输出所有说明，与 `anyhow` 的 `{:#}` 相同。
+//! 直接用 `?` 转换的外部错误是透明的，输出及来源都与原来的错误相同，不会重复
+
+use std::{error::Error as StdError, fmt::Display, io};
+use thiserror::Error;
+
+/// 错误的来源
+pub type Source = Box<dyn StdError + Send + Sync + 'static>;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// 错误的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// 配置文件或命令行参数错误
+    Config,
+    /// 读写文件
+    Io,
+    /// 网络错误，比如无法连接、超时
+    Network,
+    /// 无法解析页面、json 或者保存的数据
+    Parse,
+    /// 登录失败
+    Auth,
+    /// 无法获取题图、计算或提交答案
+    Captcha,
+    /// 提醒发送失败
+    Notification,
+    /// 用户跳过或取消，不是签到本身的问题
+    Cancelled,
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    /// 配置文件或命令行参数错误
+    #[error("{msg}")]
+    Config {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 读写文件
+    #[error("{msg}")]
+    Io {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 网络错误，比如无法连接、超时
+    #[error("{msg}")]
+    Network {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 无法解析页面、json 或者保存的数据
+    #[error("{msg}")]
+    Parse {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 登录失败
+    #[error("{msg}")]
+    Auth {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 无法获取题图、计算或提交答案
+    #[error("{msg}")]
+    Captcha {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 提醒发送失败
+    #[error("{msg}")]
+    Notification {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    /// 用户跳过或取消，不是签到本身的问题
+    #[error("{msg}")]
+    Cancelled {
+        msg: String,
+        #[source]
+        source: Option<Source>,
+    },
+    #[error(transparent)]
+    StdIo(#[from] io::Error),
+    /// 无法解码响应是解析错误，无法生成请求是配置错误，其他都是网络错误
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[cfg(feature = "mock")]
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+    #[error(transparent)]
+    HttpBuild(#[from] http::Error),
+    #[error(transparent)]
+    HeaderName(#[from] reqwest::header::InvalidHeaderName),
+    #[error(transparent)]
+    HeaderValue(#[from] reqwest::header::InvalidHeaderValue),
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    UrlEncoded(#[from] serde_urlencoded::ser::Error),
+    #[error(transparent)]
+    Url(#[from] url::ParseError),
+    #[error(transparent)]
+    FromUtf8(#[from] std::string::FromUtf8Error),
+    #[error(transparent)]
+    Utf8(#[from] std::str::Utf8Error),
+    #[error(transparent)]
+    Env(#[from] std::env::VarError),
+    #[error(transparent)]
+    Image(#[from] image::ImageError),
+    #[error(transparent)]
+    Mail(#[from] lettre::error::Error),
+    #[error(transparent)]
+    Address(#[from] lettre::address::AddressError),
+    #[error(transparent)]
+    Smtp(#[from] lettre::transport::smtp::Error),
+}
+
+impl Error {
+    /// 指定种类及说明
+    pub fn new<M: Into<String>>(kind: ErrorKind, msg: M) -> Self {
+        let msg = msg.into();
+        let source = None;
+        match kind {
+            ErrorKind::Config => Self::Config { msg, source },
+            ErrorKind::Io => Self::Io { msg, source },
+            ErrorKind::Network => Self::Network { msg, source },
+            ErrorKind::Parse => Self::Parse { msg, source },
+            ErrorKind::Auth => Self::Auth { msg, source },
+            ErrorKind::Captcha => Self::Captcha { msg, source },
+            ErrorKind::Notification => Self::Notification { msg, source },
+            ErrorKind::Cancelled => Self::Cancelled { msg, source },
+        }
+    }
+
+    pub fn config<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Config, msg)
+    }
+
+    pub fn io<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Io, msg)
+    }
+
+    pub fn network<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Network, msg)
+    }
+
+    pub fn parse<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Parse, msg)
+    }
+
+    pub fn auth<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Auth, msg)
+    }
+
+    pub fn captcha<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Captcha, msg)
+    }
+
+    pub fn notification<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Notification, msg)
+    }
+
+    pub fn cancelled<M: Into<String>>(msg: M) -> Self {
+        Self::new(ErrorKind::Cancelled, msg)
+    }
+
+    /// 错误的种类，外部的错误按来源区分
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Config { .. } => ErrorKind::Config,
+            Self::Io { .. } | Self::StdIo(_) => ErrorKind::Io,
+            Self::Network { .. } | Self::HttpBuild(_) => ErrorKind::Network,
+            #[cfg(feature = "mock")]
+            Self::Hyper(_) => ErrorKind::Network,
+            Self::Parse { .. }
+            | Self::Json(_)
+            | Self::UrlEncoded(_)
+            | Self::Url(_)
+            | Self::FromUtf8(_)
+            | Self::Utf8(_) => ErrorKind::Parse,
+            Self::Auth { .. } => ErrorKind::Auth,
+            Self::Captcha { .. } | Self::Image(_) => ErrorKind::Captcha,
+            Self::Notification { .. } | Self::Mail(_) | Self::Address(_) | Self::Smtp(_) => {
+                ErrorKind::Notification
+            }
+            Self::Cancelled { .. } => ErrorKind::Cancelled,
+            Self::Http(e) if e.is_decode() => ErrorKind::Parse,
+            Self::Http(e) if e.is_builder() => ErrorKind::Config,
+            Self::Http(_) => ErrorKind::Network,
+            Self::HeaderName(_)
+            | Self::HeaderValue(_)
+            | Self::TomlDe(_)
+            | Self::TomlSer(_)
+            | Self::Env(_) => ErrorKind::Config,
+        }
+    }
+
+    /// 设置来源，外部的错误先转换为同种类、同说明的错误
+    pub fn with_source<E: Into<Source>>(self, source: E) -> Self {
+        let source: Source = source.into();
+        match self {
+            Self::Config { msg, .. } => Self::Config {
+                msg,
+                source: Some(source),
+            },
+            Self::Io { msg, .. } => Self::Io {
+                msg,
+                source: Some(source),
+            },
+            Self::Network { msg, .. } => Self::Network {
+                msg,
+                source: Some(source),
+            },
+            Self::Parse { msg, .. } => Self::Parse {
+                msg,
+                source: Some(source),
+            },
+            Self::Auth { msg, .. } => Self::Auth {
+                msg,
+                source: Some(source),
+            },
+            Self::Captcha { msg, .. } => Self::Captcha {
+                msg,
+                source: Some(source),
+            },
+            Self::Notification { msg, .. } => Self::Notification {
+                msg,
+                source: Some(source),
+            },
+            Self::Cancelled { msg, .. } => Self::Cancelled {
+                msg,
+                source: Some(source),
+            },
+            e => Self::new(e.kind(), e.to_string()).with_source(source),
+        }
+    }
+
+    /// 加上说明，种类不变，原来的错误作为来源
+    pub fn context<M: Into<String>>(self, msg: M) -> Self {
+        Self::new(self.kind(), msg).with_source(self)
+    }
+
+    /// 依次输出说明及所有来源
+    pub fn report(&self) -> Report<'_> {
+        Report(self)
+    }
+}
+
+/// 输出说明及所有来源，用 `: ` 分开
+#[derive(Debug)]
+pub struct Report<'a>(&'a Error);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)?;
+        let mut source = self.0.source();
+        while let Some(e) = source {
+            write!(f, ": {}", e)?;
+            source = e.source();
+        }
+        Ok(())
+    }
+}
+
+/// 为 `Result` 加上说明
+pub trait Context<T> {
+    /// 加上说明，种类由原来的错误决定
+    fn context<M: Into<String>>(self, msg: M) -> Result<T>;
+
+    /// 加上说明并指定种类，比如 `.context_as(Error::config, "无法读取配置文件")`
+    fn context_as<M: Into<String>>(self, kind: fn(String) -> Error, msg: M) -> Result<T>;
+}
+
+impl<T, E: Into<Error>> Context<T> for std::result::Result<T, E> {
+    fn context<M: Into<String>>(self, msg: M) -> Result<T> {
+        self.map_err(|e| e.into().context(msg))
+    }
+
+    fn context_as<M: Into<String>>(self, kind: fn(String) -> Error, msg: M) -> Result<T> {
+        self.map_err(|e| kind(msg.into()).with_source(e.into()))
+    }
+}
+
+#[cfg(test)]
+mod error_test {
+    use super::*;
+
+    #[test]
+    fn context_test() {
+        let e: Result<()> = Err(io::Error::new(io::ErrorKind::NotFound, "not found").into());
+        let e = e.context("无法读取").context("签到失败").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Io);
+        assert_eq!(e.report().to_string(), "签到失败: 无法读取: not found");
+
+        let e = std::fs::read("/nonexistent/tjuptatt")
+            .context_as(Error::config, "无法读取配置文件")
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Config);
+        assert!(matches!(
+            e.source().unwrap().downcast_ref::<Error>(),
+            Some(Error::StdIo(_))
+        ));
+    }
+
+    #[test]
+    fn transparent_test() {
+        let e: Error = io::Error::new(io::ErrorKind::NotFound, "not found").into();
+        assert_eq!(e.to_string(), "not found");
+        // 标准的输出方式不会重复原来的说明
+        assert!(e.source().is_none());
+        assert_eq!(e.report().to_string(), "not found");
+        let e = e.context("无法读取");
+        assert_eq!(e.source().unwrap().to_string(), "not found");
+        assert!(e.source().unwrap().source().is_none());
+
+        let e: Error = reqwest::Client::new()
+            .get("not a url")
+            .build()
+            .unwrap_err()
+            .into();
+        assert_eq!(e.kind(), ErrorKind::Config);
+    }
+}
@@ -0,0 +1,382 @@
+//! 在终端显示多个用户的签到进度
+//!
+//! 每个用户一行，显示阶段、第几次尝试及结果，结束后输出汇总表。
+//! 显示时标准错误的日志先暂存，结束后再输出，避免打乱进度
+
+use crate::{
+    logger,
+    progress::{Event, Progress},
+    report::UserOutcome,
+};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+/// 用户名显示的宽度
+const USER_WIDTH: usize = 16;
+/// 阶段之前的状态、用户名及尝试次数的宽度
+const PREFIX_WIDTH: usize = 6 + 1 + USER_WIDTH + 1 + 5 + 1;
+/// 无法获取终端宽度时使用
+const DEFAULT_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Waiting,
+    Running,
+    Success,
+    Failed,
+}
+
+/// 一个用户的进度
+#[derive(Debug, Clone)]
+struct Line {
+    user: String,
+    status: Status,
+    stage: String,
+    attempt: u8,
+    retry: u8,
+    /// 选项的个数
+    options: usize,
+    /// 已经比较的选项个数
+    scored: usize,
+    /// 提交的答案的相似度
+    score: Option<f64>,
+}
+
+impl Line {
+    fn new(user: &str) -> Self {
+        Self {
+            user: user.to_owned(),
+            status: Status::Waiting,
+            stage: "等待".into(),
+            attempt: 0,
+            retry: 0,
+            options: 0,
+            scored: 0,
+            score: None,
+        }
+    }
+
+    fn update(&mut self, event: &Event) {
+        self.stage = match event {
+            Event::Started => {
+                self.status = Status::Running;
+                "开始".into()
+            }
+            Event::AttemptStarted { attempt, retry } => {
+                self.status = Status::Running;
+                self.attempt = *attempt;
+                self.retry = *retry;
+                self.scored = 0;
+                "开始".into()
+            }
+            Event::LoginStarted => "获取签到页面".into(),
+            Event::LoginSucceeded => "已登录".into(),
+            Event::PageParsed { options } => {
+                self.options = *options;
+                format!("比较选项 0/{}", options)
+            }
+            Event::OptionScored { .. } => {
+                self.scored += 1;
+                format!("比较选项 {}/{}", self.scored, self.options)
+            }
+            Event::AnswerSubmitted {
+                answer,
+                score,
+                accepted,
+            } => {
+                self.score = *score;
+                if *accepted {
+                    format!("已提交: {}", answer)
+                } else {
+                    format!("答案错误: {}", answer)
+                }
+            }
+            Event::RetryScheduled { attempt, error } => {
+                format!("第 {} 次失败: {}", attempt, error)
+            }
+            Event::Finished {
+                answer,
+                attempts,
+                error,
+            } => {
+                self.attempt = *attempts;
+                match (answer, error) {
+                    (Some(answer), _) => {
+                        self.status = Status::Success;
+                        answer.clone()
+                    }
+                    (None, error) => {
+                        self.status = Status::Failed;
+                        error.clone().unwrap_or_default()
+                    }
+                }
+            }
+        };
+    }
+}
+
+impl Line {
+    /// 一行的内容，超过终端宽度 `width` 的部分截断，否则会换行，无法覆盖
+    fn render(&self, width: usize) -> String {
+        let status = match self.status {
+            Status::Waiting => "[等待]",
+            Status::Running => "\x1b[36m[进行]\x1b[0m",
+            Status::Success => "\x1b[32m[成功]\x1b[0m",
+            Status::Failed => "\x1b[31m[失败]\x1b[0m",
+        };
+        let attempt = match self.attempt {
+            0 => "-".to_owned(),
+            n => format!("{}/{}", n, self.retry),
+        };
+        // 最后一格不使用，有的终端写满一行后会换行
+        let stage_width = width.saturating_sub(PREFIX_WIDTH + 1);
+        format!(
+            "{} {} {:<5} {}",
+            status,
+            pad(&truncate(&self.user, USER_WIDTH), USER_WIDTH),
+            attempt,
+            truncate(&self.stage, stage_width)
+        )
+    }
+}
+
+/// 终端中的进度
+#[derive(Debug)]
+pub struct LiveView {
+    lines: Arc<Mutex<Vec<Line>>>,
+}
+
+impl LiveView {
+    /// 开始显示 `users` 的进度，返回的 `Progress` 需要设置给每个用户
+    pub fn start(users: &[&str]) -> (Self, Progress) {
+        logger::hold_stderr();
+        let lines = Arc::new(Mutex::new(
+            users.iter().map(|u| Line::new(u)).collect::<Vec<_>>(),
+        ));
+        if let Ok(lines) = lines.lock() {
+            draw(&lines, false);
+        }
+
+        let progress = Progress::new({
+            let lines = lines.clone();
+            move |e| {
+                let Ok(mut lines) = lines.lock() else {
+                    return;
+                };
+                if let Some(line) = lines.iter_mut().find(|l| l.user == e.user) {
+                    line.update(&e.event);
+                }
+                draw(&lines, true);
+            }
+        });
+        (Self { lines }, progress)
+    }
+
+    /// 结束显示，输出暂存的日志及汇总表
+    pub fn finish(self, outcomes: &[UserOutcome]) {
+        logger::release_stderr();
+        let scores = self
+            .lines
+            .lock()
+            .map(|lines| {
+                outcomes
+                    .iter()
+                    .map(|o| {
+                        lines
+                            .iter()
+                            .find(|l| l.user == o.user)
+                            .and_then(|l| l.score)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        eprint!("{}", table(outcomes, &scores));
+    }
+}
+
+/// 输出所有行，`redraw` 为 true 时覆盖上次输出的
+fn draw(lines: &[Line], redraw: bool) {
+    if lines.is_empty() {
+        return;
+    }
+    let width = term_width();
+    let mut out = String::new();
+    if redraw {
+        out.push_str(&format!("\x1b[{}A", lines.len()));
+    }
+    for line in lines {
+        out.push_str(&format!("\x1b[2K{}\n", line.render(width)));
+    }
+    let mut stderr = std::io::stderr().lock();
+    let _r = stderr.write_all(out.as_bytes());
+    let _r = stderr.flush();
+}
+
+/// 标准错误所在终端的宽度
+fn term_width() -> usize {
+    #[cfg(unix)]
+    let size = {
+        use std::os::unix::io::AsRawFd;
+        terminal_size::terminal_size_using_fd(std::io::stderr().as_raw_fd())
+    };
+    #[cfg(windows)]
+    let size = {
+        use std::os::windows::io::AsRawHandle;
+        terminal_size::terminal_size_using_handle(std::io::stderr().as_raw_handle())
+    };
+    #[cfg(not(any(unix, windows)))]
+    let size = terminal_size::terminal_size();
+    size.map_or(DEFAULT_WIDTH, |(w, _)| w.0 as usize)
+}
+
+/// 汇总表，`scores` 为每个用户提交的答案的相似度
+fn table(outcomes: &[UserOutcome], scores: &[Option<f64>]) -> String {
+    let rows = outcomes
+        .iter()
+        .enumerate()
+        .map(|(n, o)| {
+            [
+                o.user.clone(),
+                if o.success { "成功" } else { "失败" }.to_owned(),
+                o.answer.clone().unwrap_or_else(|| "-".into()),
+                scores
+                    .get(n)
+                    .copied()
+                    .flatten()
+                    .map_or_else(|| "-".into(), |s| format!("{:.2}%", s)),
+                format!("{:.1}s", o.duration_ms as f64 / 1000.0),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["用户", "结果", "答案", "相似度", "耗时"].map(String::from);
+    let mut widths = [0; 5];
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        for (w, cell) in widths.iter_mut().zip(row) {
+            *w = (*w).max(width(cell));
+        }
+    }
+
+    let mut out = String::new();
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let cells = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, w)| pad(cell, w))
+            .collect::<Vec<_>>();
+        out.push_str(cells.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// 在终端中的宽度，非 ascii 字符按两格计算
+fn width(s: &str) -> usize {
+    s.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+/// 用空格补齐到 `w` 格
+fn pad(s: &str, w: usize) -> String {
+    format!("{}{}", s, " ".repeat(w.saturating_sub(width(s))))
+}
+
+/// 超过 `w` 格的部分用 `...` 代替，只保留第一行
+fn truncate(s: &str, w: usize) -> String {
+    let s = s.lines().next().unwrap_or_default();
+    if width(s) <= w {
+        return s.to_owned();
+    }
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        used += width(c.encode_utf8(&mut [0; 4]));
+        if used > w.saturating_sub(3) {
+            break;
+        }
+        out.push(c);
+    }
+    out.push_str("...");
+    out
+}
+
+#[cfg(test)]
+mod live_test {
+    use super::*;
+
+    #[test]
+    fn line_test() {
+        let mut line = Line::new("user");
+        let events = [
+            Event::AttemptStarted {
+                attempt: 1,
+                retry: 2,
+            },
+            Event::LoginStarted,
+            Event::LoginSucceeded,
+            Event::PageParsed { options: 4 },
+            Event::OptionScored {
+                name: "a".into(),
+                score: Some(12.0),
+                error: None,
+            },
+        ];
+        for e in events.iter() {
+            line.update(e);
+        }
+        assert_eq!(line.status, Status::Running);
+        assert_eq!(line.stage, "比较选项 1/4");
+
+        line.update(&Event::AnswerSubmitted {
+            answer: "b".into(),
+            score: Some(93.5),
+            accepted: false,
+        });
+        assert_eq!(line.stage, "答案错误: b");
+        line.update(&Event::AnswerSubmitted {
+            answer: "a".into(),
+            score: Some(96.5),
+            accepted: true,
+        });
+        line.update(&Event::Finished {
+            answer: Some("a".into()),
+            attempts: 1,
+            error: None,
+        });
+        assert_eq!(line.status, Status::Success);
+        assert_eq!(line.score, Some(96.5));
+        assert!(line.render(80).contains(" 1/2 "));
+
+        // 不超过终端的宽度
+        line.stage = "很长的阶段".repeat(10);
+        assert!(width(&line.render(60)) <= 60 + width("\x1b[32m\x1b[0m"));
+        assert!(line.render(60).ends_with("..."));
+    }
+
+    #[test]
+    fn table_test() {
+        let outcome = |user: &str, answer: Option<&str>| UserOutcome {
+            user: user.into(),
+            success: answer.is_some(),
+            answer: answer.map(String::from),
+            attempts: 1,
+            duration_ms: 1500,
+            error: None,
+            network_error: false,
+            scores: vec![],
+        };
+        let table = table(
+            &[outcome("alice", Some("狮子王")), outcome("bob", None)],
+            &[Some(96.5), None],
+        );
+        assert_eq!(
+            table,
+            "\
+用户   结果  答案    相似度  耗时
+alice  成功  狮子王  96.50%  1.5s
+bob    失败  -       -       1.5s
+"
+        );
+        assert_eq!(truncate("狮子王 The Lion King", 10), "狮子王 ...");
+    }
+}
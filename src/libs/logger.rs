@@ -0,0 +1,444 @@
+//! 日志
+//!
+//! 支持 `RUST_LOG` 格式的过滤、文本或 json 格式、输出到文件
+//!
+//! 签到时每行日志会带上对应的用户
+
+use crate::command::DIRS;
+use crate::error::{Context, Error, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    fs::{create_dir_all, read_dir, remove_file, File, OpenOptions},
+    future::Future,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{Duration, SystemTime},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime, UtcOffset};
+
+/// 日志文件的前缀
+const LOG_FILE_PREFIX: &str = "tjuptatt-";
+
+/// 本地时区，日志的时间及滚动的日期都使用本地时间
+static LOCAL_OFFSET: OnceLock<UtcOffset> = OnceLock::new();
+
+/// 获取本地时区
+///
+/// 需要在启动其他线程之前调用，否则无法获取，使用 UTC
+pub fn init_local_offset() {
+    let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+    let _r = LOCAL_OFFSET.set(offset);
+}
+
+fn now() -> OffsetDateTime {
+    let offset = LOCAL_OFFSET.get().copied().unwrap_or(UtcOffset::UTC);
+    OffsetDateTime::now_utc().to_offset(offset)
+}
+
+/// 默认就比较吵的模块
+const NOISY_MODULES: [&str; 6] = [
+    "reqwest",
+    "cookie_store",
+    "selectors",
+    "html5ever",
+    "rustls",
+    "hyper",
+];
+
+/// 暂存的标准错误的日志，`None` 表示直接输出
+static HELD: Mutex<Option<Vec<String>>> = Mutex::new(None);
+
+/// 暂存之后输出到标准错误的日志，比如终端正在显示进度时
+pub fn hold_stderr() {
+    if let Ok(mut held) = HELD.lock() {
+        held.get_or_insert_with(Vec::new);
+    }
+}
+
+/// 输出暂存的日志，之后直接输出
+pub fn release_stderr() {
+    let lines = HELD.lock().ok().and_then(|mut held| held.take());
+    let mut stderr = std::io::stderr().lock();
+    for line in lines.into_iter().flatten() {
+        let _r = writeln!(stderr, "{}", line);
+    }
+}
+
+tokio::task_local! {
+    /// 当前正在签到的用户
+    static USER_ID: String;
+}
+
+/// 在 `fut` 中的日志都会带上用户 `user`
+pub async fn with_user<F>(user: &str, fut: F) -> F::Output
+where
+    F: Future,
+{
+    USER_ID.scope(user.to_string(), fut).await
+}
+
+/// 日志格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// 日志输出位置
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogDest {
+    Stderr,
+    /// 追加写入指定的文件
+    File(PathBuf),
+    /// 按天滚动写入 `DIRS.state_dir()/logs`，`keep_days` 不为 0 时删除更早的日志
+    Rotating {
+        keep_days: u64,
+    },
+}
+
+/// `RUST_LOG` 格式的过滤规则，比如 `info,libs::bot=debug,reqwest=off`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// 模块及其等级，按模块名长度从长到短排列
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// 以 `default` 为默认等级，并且降低吵闹模块的等级
+    pub fn new(default: LevelFilter) -> Self {
+        let mut filter = Self {
+            default,
+            modules: vec![],
+        };
+        for m in NOISY_MODULES {
+            filter.set_module(m, LevelFilter::Error);
+        }
+        filter
+    }
+
+    /// 在现有规则上应用 `RUST_LOG` 格式的规则
+    pub fn parse(mut self, spec: &str) -> Result<Self> {
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        return Err(Error::config(format!("日志规则缺少模块名: {}", directive)));
+                    }
+                    let level = parse_level(level)?;
+                    self.set_module(module, level);
+                }
+                None => match parse_level(directive) {
+                    Ok(level) => self.default = level,
+                    // 只有模块名，表示此模块的所有日志
+                    Err(_) => self.set_module(directive, LevelFilter::Trace),
+                },
+            }
+        }
+        Ok(self)
+    }
+
+    fn set_module(&mut self, module: &str, level: LevelFilter) {
+        self.modules.retain(|(m, _)| m != module);
+        self.modules.push((module.to_string(), level));
+        self.modules
+            .sort_by_key(|(m, _)| std::cmp::Reverse(m.len()));
+    }
+
+    /// `target` 对应的等级
+    fn level_of(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(m, _)| {
+                target == m
+                    || target
+                        .strip_prefix(m.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// 所有规则中最详细的等级
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level
+        .trim()
+        .parse()
+        .map_err(|_| Error::config(format!("无效的日志等级: {}", level)))
+}
+
+/// 日志的输出
+enum Writer {
+    Stderr,
+    File(File),
+    Rotating {
+        dir: PathBuf,
+        date: String,
+        file: File,
+        keep_days: u64,
+    },
+}
+
+impl Writer {
+    fn new(dest: &LogDest) -> Result<Self> {
+        Ok(match dest {
+            LogDest::Stderr => Self::Stderr,
+            LogDest::File(path) => Self::File(open_append(path)?),
+            LogDest::Rotating { keep_days } => {
+                let dir = DIRS.state_dir().join("logs");
+                create_dir_all(&dir).context(format!("无法创建日志文件夹: {}", dir.display()))?;
+                remove_old_logs(&dir, *keep_days);
+                let date = today();
+                let file = open_append(&rotating_path(&dir, &date))?;
+                Self::Rotating {
+                    dir,
+                    date,
+                    file,
+                    keep_days: *keep_days,
+                }
+            }
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        let _r = match self {
+            Self::Stderr => match HELD.lock().as_deref_mut() {
+                Ok(Some(held)) => {
+                    held.push(line.to_owned());
+                    Ok(())
+                }
+                _ => writeln!(std::io::stderr().lock(), "{}", line),
+            },
+            Self::File(file) => writeln!(file, "{}", line),
+            Self::Rotating {
+                dir,
+                date,
+                file,
+                keep_days,
+            } => {
+                let now = today();
+                if *date != now {
+                    if let Ok(new_file) = open_append(&rotating_path(dir, &now)) {
+                        *file = new_file;
+                        *date = now;
+                        remove_old_logs(dir, *keep_days);
+                    }
+                }
+                writeln!(file, "{}", line)
+            }
+        };
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("无法打开日志文件: {}", path.display()))
+}
+
+/// 本地的日期
+fn today() -> String {
+    let now = now();
+    format!(
+        "{:04}-{:02}-{:02}",
+        now.year(),
+        now.month() as u8,
+        now.day()
+    )
+}
+
+fn rotating_path(dir: &Path, date: &str) -> PathBuf {
+    dir.join(format!("{}{}.log", LOG_FILE_PREFIX, date))
+}
+
+/// 删除超过 `keep_days` 天的滚动日志，0 表示不删除
+fn remove_old_logs(dir: &Path, keep_days: u64) {
+    if keep_days == 0 {
+        return;
+    }
+    let Ok(entries) = read_dir(dir) else {
+        return;
+    };
+    let keep = Duration::from_secs(keep_days * 24 * 60 * 60);
+    for entry in entries.flatten() {
+        let is_log = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|n| n.starts_with(LOG_FILE_PREFIX) && n.ends_with(".log"));
+        let expired = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .is_some_and(|age| age > keep);
+        if is_log && expired {
+            let _r = remove_file(entry.path());
+        }
+    }
+}
+
+/// 日志
+pub struct Logger {
+    filter: LogFilter,
+    format: LogFormat,
+    color: bool,
+    writer: Mutex<Writer>,
+}
+
+impl Logger {
+    pub fn new(filter: LogFilter, format: LogFormat, dest: &LogDest) -> Result<Self> {
+        let color = format == LogFormat::Text
+            && *dest == LogDest::Stderr
+            && std::io::stderr().is_terminal();
+        Ok(Self {
+            filter,
+            format,
+            color,
+            writer: Mutex::new(Writer::new(dest)?),
+        })
+    }
+
+    /// 设置为全局的日志
+    pub fn init(self) -> Result<()> {
+        log::set_max_level(self.filter.max_level());
+        log::set_boxed_logger(Box::new(self))
+            .map_err(|e| Error::config(format!("无法初始化日志: {}", e)))
+    }
+
+    fn format(&self, record: &Record) -> String {
+        let ts = now().format(&Rfc3339).unwrap_or_default();
+        let user = USER_ID.try_with(|u| u.clone()).ok();
+        match self.format {
+            LogFormat::Json => serde_json::json!({
+                "ts": ts,
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "user": user,
+                "msg": record.args().to_string(),
+            })
+            .to_string(),
+            LogFormat::Text => {
+                let level = if self.color {
+                    let color = match record.level() {
+                        Level::Error => "31",
+                        Level::Warn => "33",
+                        Level::Info => "32",
+                        Level::Debug => "36",
+                        Level::Trace => "35",
+                    };
+                    format!("\x1b[{}m{:<5}\x1b[0m", color, record.level())
+                } else {
+                    format!("{:<5}", record.level())
+                };
+                match user {
+                    Some(user) => format!(
+                        "{} {} [{}] [{}] {}",
+                        ts,
+                        level,
+                        record.target(),
+                        user,
+                        record.args()
+                    ),
+                    None => format!("{} {} [{}] {}", ts, level, record.target(), record.args()),
+                }
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_of(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = self.format(record);
+        if let Ok(mut writer) = self.writer.lock() {
+            writer.write_line(&line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writer) = self.writer.lock() {
+            let _r = match &mut *writer {
+                Writer::Stderr => std::io::stderr().flush(),
+                Writer::File(file) | Writer::Rotating { file, .. } => file.flush(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod logger_test {
+    use super::*;
+
+    #[test]
+    fn filter_test() {
+        let filter = LogFilter::new(LevelFilter::Info)
+            .parse("warn,libs::bot=debug,hyper")
+            .unwrap();
+        assert_eq!(filter.level_of("libs::config"), LevelFilter::Warn);
+        assert_eq!(filter.level_of("libs::bot"), LevelFilter::Debug);
+        assert_eq!(filter.level_of("libs::botx"), LevelFilter::Warn);
+        assert_eq!(filter.level_of("hyper::proto"), LevelFilter::Trace);
+        assert_eq!(filter.level_of("reqwest::connect"), LevelFilter::Error);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+        assert!(LogFilter::new(LevelFilter::Info).parse("a=loud").is_err());
+    }
+
+    #[test]
+    fn invalid_filter_test() {
+        let filter = LogFilter::new(LevelFilter::Info);
+        for spec in ["a=", "=debug", "a=debug=b", "info,libs=verbose"] {
+            assert!(filter.clone().parse(spec).is_err(), "{}", spec);
+        }
+        // 空的规则忽略
+        assert_eq!(filter.clone().parse(" , ,").unwrap(), filter);
+    }
+
+    #[test]
+    fn remove_old_logs_test() {
+        let dir = std::env::temp_dir().join(format!("tjuptatt_logs_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let old = rotating_path(&dir, "2000-01-01");
+        let new = rotating_path(&dir, &today());
+        let other = dir.join("other.log");
+        let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        for path in [&old, &new, &other] {
+            open_append(path).unwrap();
+        }
+        for path in [&old, &other] {
+            File::options()
+                .write(true)
+                .open(path)
+                .unwrap()
+                .set_modified(two_days_ago)
+                .unwrap();
+        }
+
+        // 0 表示不删除
+        remove_old_logs(&dir, 0);
+        assert!(old.exists());
+
+        remove_old_logs(&dir, 1);
+        assert!(!old.exists());
+        assert!(new.exists());
+        // 不是滚动日志的文件不删除
+        assert!(other.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
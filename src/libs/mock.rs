@@ -0,0 +1,440 @@
+//! 模拟 tjupt 及豆瓣搜索建议的本地服务器，用于端到端测试
+//!
+//! 提供 `login.php`、`takelogin.php`、`attendance.php`、题图、海报及 `j/subject_suggest`，
+//! 通过 [`Scenario`] 模拟签到成功、已经签到、密码错误、需要补签及页面改版
+
+use crate::error::{Error, Result};
+use hyper::{
+    header::{CONTENT_TYPE, COOKIE, HOST, LOCATION, SET_COOKIE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use image::{ImageFormat, Rgb, RgbImage};
+use reqwest::Url;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fmt::Display,
+    io::Cursor,
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::oneshot;
+
+/// 可以登录的用户名
+pub const MOCK_USER: &str = "mock";
+/// 可以登录的密码
+pub const MOCK_PWD: &str = "mock_pwd";
+
+/// 登录后设置的 cookie，与 tjupt 相同带有有效期
+const SESSION: &str = "c_secure_uid=1";
+
+/// 模拟的情况
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// 正常签到
+    Success,
+    /// 今天已经签到过
+    Attended,
+    /// 密码错误，无法登录
+    WrongPassword,
+    /// 需要补签
+    Makeup,
+    /// 签到页面改版，找不到题图
+    LayoutChange,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 5] = [
+        Self::Success,
+        Self::Attended,
+        Self::WrongPassword,
+        Self::Makeup,
+        Self::LayoutChange,
+    ];
+}
+
+impl Display for Scenario {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => write!(f, "success"),
+            Self::Attended => write!(f, "attended"),
+            Self::WrongPassword => write!(f, "wrong-password"),
+            Self::Makeup => write!(f, "makeup"),
+            Self::LayoutChange => write!(f, "layout-change"),
+        }
+    }
+}
+
+impl FromStr for Scenario {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.to_string() == s)
+            .ok_or_else(|| Error::config(format!("不支持的情况: {}", s)))
+    }
+}
+
+/// 签到页面的一个选项
+struct Movie {
+    value: &'static str,
+    /// 选项名
+    name: &'static str,
+    /// 豆瓣的中文名
+    title: &'static str,
+    sub_title: &'static str,
+    year: &'static str,
+    /// 海报的图案
+    seed: u8,
+}
+
+const MOVIES: [Movie; 4] = [
+    Movie {
+        value: "1",
+        name: "流浪地球",
+        title: "流浪地球",
+        sub_title: "The Wandering Earth",
+        year: "2019",
+        seed: 1,
+    },
+    Movie {
+        value: "2",
+        name: "狮子王 The Lion King (1994)",
+        title: "狮子王",
+        sub_title: "The Lion King",
+        year: "1994",
+        seed: 2,
+    },
+    Movie {
+        value: "3",
+        name: "霸王别姬",
+        title: "霸王别姬",
+        sub_title: "Farewell My Concubine",
+        year: "1993",
+        seed: 3,
+    },
+    Movie {
+        value: "4",
+        name: "千与千寻",
+        title: "千と千尋の神隠し",
+        sub_title: "Spirited Away",
+        year: "2001",
+        seed: 4,
+    },
+];
+
+/// 正确答案的值
+pub const ANSWER: &str = "2";
+
+/// 正确答案的重拍版，豆瓣会排在前面，海报不同
+const REMAKE: (&str, u8) = ("2019", 5);
+
+#[derive(Serialize)]
+struct Suggest {
+    img: String,
+    title: &'static str,
+    sub_title: &'static str,
+    year: &'static str,
+}
+
+/// 服务器的状态
+#[derive(Debug)]
+struct State {
+    scenario: Scenario,
+    /// 收到的答案
+    posted: Mutex<Vec<String>>,
+}
+
+/// 运行中的模拟服务器，drop 时关闭
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<State>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockServer {
+    /// 在 127.0.0.1 的随机端口上启动
+    pub async fn start(scenario: Scenario) -> Result<Self> {
+        Self::bind(([127, 0, 0, 1], 0).into(), scenario).await
+    }
+
+    /// 在 `addr` 上启动
+    pub async fn bind(addr: SocketAddr, scenario: Scenario) -> Result<Self> {
+        let state = Arc::new(State {
+            scenario,
+            posted: Mutex::default(),
+        });
+        let service_state = state.clone();
+        let make_svc = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(&state, req).await) }
+                }))
+            }
+        });
+
+        let server = Server::try_bind(&addr)?.serve(make_svc);
+        let addr = server.local_addr();
+        let (tx, rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let graceful = server.with_graceful_shutdown(async {
+                rx.await.ok();
+            });
+            if let Err(e) = graceful.await {
+                log::warn!("模拟服务器出错: {}", e);
+            }
+        });
+
+        Ok(Self {
+            addr,
+            state,
+            shutdown: Some(tx),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 站点地址，用于 `site_url`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// 搜索建议的链接，用于 `suggest_url`
+    pub fn suggest_url(&self) -> String {
+        format!("{}/j/subject_suggest", self.url())
+    }
+
+    pub fn scenario(&self) -> Scenario {
+        self.state.scenario
+    }
+
+    /// 收到的所有答案
+    pub fn posted(&self) -> Vec<String> {
+        self.state
+            .posted
+            .lock()
+            .map(|p| p.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+async fn handle(state: &State, req: Request<Body>) -> Response<Body> {
+    let logged_in = req
+        .headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.split(';').any(|c| c.trim() == SESSION));
+    // 海报的链接需要是完整的
+    let base = format!(
+        "http://{}",
+        req.headers()
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("127.0.0.1")
+    );
+    let method = req.method().clone();
+    let path = req.uri().path().to_owned();
+    let query = params(req.uri().query().unwrap_or_default());
+    let form = match hyper::body::to_bytes(req.into_body()).await {
+        Ok(b) => params(&String::from_utf8_lossy(&b)),
+        Err(_) => HashMap::new(),
+    };
+
+    match (method, path.as_str()) {
+        (Method::GET, "/login.php") => html(LOGIN_PAGE),
+        (Method::POST, "/takelogin.php") => {
+            let ok = state.scenario != Scenario::WrongPassword
+                && form.get("username").map(String::as_str) == Some(MOCK_USER)
+                && form.get("password").map(String::as_str) == Some(MOCK_PWD);
+            if ok {
+                let returnto = form
+                    .get("returnto")
+                    .map(String::as_str)
+                    .unwrap_or("index.php");
+                Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header(LOCATION, format!("/{}", returnto))
+                    .header(SET_COOKIE, format!("{}; Path=/; Max-Age=86400", SESSION))
+                    .body(Body::empty())
+                    .unwrap_or_default()
+            } else {
+                html("<html><body><h2>登录失败</h2><p>用户名或密码不正确</p></body></html>")
+            }
+        }
+        (_, "/attendance.php") if !logged_in => redirect("/login.php?returnto=attendance.php"),
+        (Method::GET, "/attendance.php") => html(&attendance_page(state.scenario)),
+        (Method::POST, "/attendance.php") => {
+            let answer = form.get("answer").cloned().unwrap_or_default();
+            let correct = answer == ANSWER;
+            if let Ok(mut posted) = state.posted.lock() {
+                posted.push(answer);
+            }
+            if correct {
+                html("<html><body><p>签到成功，获得 10 魔力值</p></body></html>")
+            } else {
+                html("<html><body><p>签到失败，答案错误</p></body></html>")
+            }
+        }
+        (Method::GET, "/captcha.png") => png(&poster(MOVIES[1].seed)),
+        (Method::GET, "/j/subject_suggest") => {
+            let q = query.get("q").cloned().unwrap_or_default();
+            json(&suggest(&q, &base))
+        }
+        (Method::GET, p) if p.starts_with("/posters/") => {
+            match p["/posters/".len()..].trim_end_matches(".png").parse() {
+                Ok(seed) => png(&poster(seed)),
+                Err(_) => not_found(),
+            }
+        }
+        _ => not_found(),
+    }
+}
+
+/// 搜索建议，名字包含豆瓣中文名或原名的都会返回
+fn suggest(q: &str, base: &str) -> Vec<Suggest> {
+    let mut res = vec![];
+    for m in MOVIES.iter() {
+        if !q.contains(m.title) && !q.contains(m.sub_title) {
+            continue;
+        }
+        if m.value == ANSWER {
+            res.push(Suggest {
+                img: format!("{}/posters/{}.png", base, REMAKE.1),
+                title: m.title,
+                sub_title: m.sub_title,
+                year: REMAKE.0,
+            });
+        }
+        res.push(Suggest {
+            img: format!("{}/posters/{}.png", base, m.seed),
+            title: m.title,
+            sub_title: m.sub_title,
+            year: m.year,
+        });
+    }
+    res
+}
+
+const LOGIN_PAGE: &str = r#"<html><body>
+<form method="post" action="takelogin.php">
+<input type="text" name="username"><input type="password" name="password">
+<input type="submit" value="登录">
+</form>
+</body></html>"#;
+
+fn attendance_page(scenario: Scenario) -> String {
+    let options = MOVIES
+        .iter()
+        .map(|m| {
+            format!(
+                r#"<input type="radio" name="answer" value="{}">{}<br>"#,
+                m.value, m.name
+            )
+        })
+        .collect::<String>();
+    match scenario {
+        Scenario::Attended => "<html><body><p>今天已签到，已连续签到 7 天</p></body></html>".into(),
+        Scenario::Makeup => r#"<html><body><p>你已经断签，需要补签</p>
+<form method="post" action="attendance.php"><input type="submit" name="makeup" value="补签"></form>
+</body></html>"#
+            .into(),
+        Scenario::LayoutChange => format!(
+            r#"<html><body><div class="captcha"><img src="/captcha.png"></div>
+<form method="post" action="attendance.php">{}<input type="submit" name="submit" value="提交"></form>
+</body></html>"#,
+            options
+        ),
+        Scenario::Success | Scenario::WrongPassword => format!(
+            r#"<html><body><table><tr><td id="outer"><img src="/captcha.png">
+<form method="post" action="attendance.php">{}<input type="submit" name="submit" value="提交"></form>
+</td></tr></table></body></html>"#,
+            options
+        ),
+    }
+}
+
+/// 不同 `seed` 的图案差别很大
+fn poster(seed: u8) -> RgbImage {
+    let block = 16 + seed as u32 * 12;
+    let color = [
+        seed.wrapping_mul(61),
+        255 - seed.wrapping_mul(47),
+        seed.wrapping_mul(97),
+    ];
+    RgbImage::from_fn(240, 400, |x, y| {
+        let on = (x / block + (y + x * seed as u32 / 4) / block) % 2 == 0;
+        if on {
+            Rgb(color)
+        } else {
+            Rgb(color.map(|c| 255 - c))
+        }
+    })
+}
+
+/// 解析 query 或表单
+fn params(s: &str) -> HashMap<String, String> {
+    Url::parse(&format!("http://localhost/?{}", s))
+        .map(|u| u.query_pairs().into_owned().collect())
+        .unwrap_or_default()
+}
+
+fn html(body: &str) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body.to_owned()))
+        .unwrap_or_default()
+}
+
+fn json<T: Serialize>(value: &T) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(value).unwrap_or_default()))
+        .unwrap_or_default()
+}
+
+fn png(img: &RgbImage) -> Response<Body> {
+    let mut buf = Vec::new();
+    if img
+        .write_to(&mut Cursor::new(&mut buf), ImageFormat::Png)
+        .is_err()
+    {
+        return not_found();
+    }
+    Response::builder()
+        .header(CONTENT_TYPE, "image/png")
+        .body(Body::from(buf))
+        .unwrap_or_default()
+}
+
+fn redirect(location: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::FOUND)
+        .header(LOCATION, location)
+        .body(Body::empty())
+        .unwrap_or_default()
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::empty())
+        .unwrap_or_default()
+}
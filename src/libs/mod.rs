@@ -2,5 +2,25 @@ pub mod bot;
 pub mod cliparser;
 pub mod command;
 pub mod config;
+pub mod cookie;
+pub mod dataset;
+pub mod debug_dump;
+pub mod doctor;
+pub mod douban;
 pub mod email_bot;
+pub mod error;
+pub mod http;
+pub mod live;
+pub mod logger;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod notify;
 pub mod picparser;
+pub mod progress;
+pub mod report;
+pub mod secret;
+pub mod similarity;
+pub mod solve;
+pub mod tape;
+pub mod termimg;
+pub mod transport;
@@ -0,0 +1,235 @@
+//! 提醒，支持邮件及 webhook
+//!
+//! 签到失败的提醒及 `notify test` 都通过 [`Notifier`] 发送
+
+use crate::{
+    config::{EmailConfig, WebhookConfig},
+    email_bot,
+    error::{Context, Error, Result},
+};
+use reqwest::Client;
+use std::fmt::Display;
+
+/// 提醒的渠道
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Email,
+    Webhook,
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Email => write!(f, "email"),
+            Self::Webhook => write!(f, "webhook"),
+        }
+    }
+}
+
+/// 一条提醒
+#[derive(Debug, Clone)]
+pub struct Notice<'a> {
+    /// 签到的用户
+    pub user: &'a str,
+    /// 邮件的收件人，没有则不发送邮件
+    pub to: Option<&'a str>,
+    pub content: &'a str,
+}
+
+/// 发送成功的详细信息
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    pub channel: Channel,
+    /// smtp 的响应，或者 http 的状态及响应
+    pub detail: String,
+}
+
+impl Display for Delivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.channel, self.detail)
+    }
+}
+
+/// 提醒
+///
+/// 没有设置的渠道不会发送
+#[derive(Debug, Default)]
+pub struct Notifier {
+    email: Option<EmailConfig>,
+    webhook: Option<WebhookConfig>,
+    client: Client,
+}
+
+impl Notifier {
+    /// `client` 用来发送 webhook
+    pub fn new(email: Option<EmailConfig>, webhook: Option<WebhookConfig>, client: Client) -> Self {
+        Self {
+            email,
+            webhook,
+            client,
+        }
+    }
+
+    /// 已经设置的渠道
+    pub fn channels(&self) -> Vec<Channel> {
+        let mut channels = vec![];
+        if self.email.is_some() {
+            channels.push(Channel::Email);
+        }
+        if self.webhook.is_some() {
+            channels.push(Channel::Webhook);
+        }
+        channels
+    }
+
+    /// 通过所有设置的渠道发送，失败的只记录日志
+    pub async fn notify(&self, notice: &Notice<'_>) {
+        for channel in self.channels() {
+            if channel == Channel::Email && notice.to.is_none() {
+                continue;
+            }
+            match self.send(channel, notice).await {
+                Ok(d) => log::debug!("发送提醒成功: {}", d),
+                Err(e) => log::error!("{} 提醒发送失败!, Err: {}", channel, e.report()),
+            }
+        }
+    }
+
+    /// 通过 `channel` 发送
+    pub async fn send(&self, channel: Channel, notice: &Notice<'_>) -> Result<Delivery> {
+        let detail = match channel {
+            Channel::Email => {
+                let Some(ref email_config) = self.email else {
+                    return Err(Error::notification("没有开启邮件提醒"));
+                };
+                let Some(to) = notice.to else {
+                    return Err(Error::notification("没有邮件收件人"));
+                };
+                let (email_config, to, content) = (
+                    email_config.clone(),
+                    to.to_owned(),
+                    notice.content.to_owned(),
+                );
+                tokio::task::spawn_blocking(move || {
+                    email_bot::send_email(&email_config, &to, &content)
+                })
+                .await
+                .map_err(|e| Error::notification("无法发送邮件").with_source(e))??
+            }
+            Channel::Webhook => {
+                let Some(ref webhook) = self.webhook else {
+                    return Err(Error::notification("没有设置 webhook"));
+                };
+                let res = self
+                    .client
+                    .post(webhook.url())
+                    .json(&serde_json::json!({
+                        "title": "TJUPT BOT STATUS",
+                        "user": notice.user,
+                        "content": notice.content,
+                    }))
+                    .send()
+                    .await
+                    .context_as(Error::notification, "无法发送 webhook")?;
+                let status = res.status();
+                let body = res.text().await.unwrap_or_default();
+                let body: String = body.chars().take(200).collect();
+                if !status.is_success() {
+                    return Err(Error::notification(format!("{} {}", status, body)));
+                }
+                format!("{} {}", status, body)
+            }
+        };
+        Ok(Delivery { channel, detail })
+    }
+}
+
+#[cfg(test)]
+mod notify_test {
+    use super::*;
+    use crate::error::ErrorKind;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    };
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    /// 记录收到的 webhook，返回 `status`
+    fn webhook_server(status: StatusCode) -> (String, Arc<Mutex<Vec<serde_json::Value>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let make = make_service_fn({
+            let received = received.clone();
+            move |_| {
+                let received = received.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                        let received = received.clone();
+                        async move {
+                            let body = hyper::body::to_bytes(req.into_body())
+                                .await
+                                .map_err(|e| Error::network("无法读取请求").with_source(e))?;
+                            if let Ok(json) = serde_json::from_slice(&body) {
+                                received.lock().unwrap().push(json);
+                            }
+                            Ok::<_, Error>(
+                                Response::builder().status(status).body(Body::from("ok"))?,
+                            )
+                        }
+                    }))
+                }
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make);
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+        (url, received)
+    }
+
+    fn notice() -> Notice<'static> {
+        Notice {
+            user: "user",
+            to: None,
+            content: "user 签到失败",
+        }
+    }
+
+    #[tokio::test]
+    async fn webhook_test() {
+        let (url, received) = webhook_server(StatusCode::OK);
+        let notifier = Notifier::new(None, Some(WebhookConfig::new(&url)), Client::new());
+        assert_eq!(notifier.channels(), vec![Channel::Webhook]);
+
+        let delivery = notifier.send(Channel::Webhook, &notice()).await.unwrap();
+        assert_eq!(delivery.to_string(), "webhook: 200 OK ok");
+        assert_eq!(
+            received.lock().unwrap()[0],
+            serde_json::json!({
+                "title": "TJUPT BOT STATUS",
+                "user": "user",
+                "content": "user 签到失败",
+            })
+        );
+
+        // 没有收件人时不发送邮件，webhook 照常发送
+        notifier.notify(&notice()).await;
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert!(notifier.send(Channel::Email, &notice()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn webhook_error_test() {
+        let (url, _) = webhook_server(StatusCode::INTERNAL_SERVER_ERROR);
+        let notifier = Notifier::new(None, Some(WebhookConfig::new(&url)), Client::new());
+        let e = notifier
+            .send(Channel::Webhook, &notice())
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Notification);
+        assert!(e.report().to_string().starts_with("500"));
+
+        assert!(Notifier::default().channels().is_empty());
+    }
+}
@@ -1,19 +1,178 @@
 //! 解析图片，获得答案
 
-use anyhow::{anyhow, Result};
+use crate::{
+    command::doubanurls,
+    douban::{self, DEFAULT_CANDIDATES},
+    error::{Error, Result},
+    similarity::{DssimMetric, SimilarityMetric},
+    tape::{self, Tape},
+    transport::{self, Transport},
+};
 use bytes::Bytes;
-use dssim::{Dssim, DssimImage, ToRGBAPLU};
-use image::ImageFormat;
-use imgref::Img;
-use load_image::ImageData;
-use reqwest::Client;
-use serde::Deserialize;
-use std::{fmt::Display, io::Cursor};
+use image::{imageops::FilterType, ImageFormat, RgbImage};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Display,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+/// 限速，保证两次请求之间至少间隔 `interval`
+///
+/// 可以在多个用户之间共享
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// 等待直到可以发送下一个请求
+    pub async fn wait(&self) {
+        if self.interval.is_zero() {
+            return;
+        }
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            tokio::time::sleep_until((*next).into()).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
+
+impl Default for RateLimiter {
+    /// 不限速
+    fn default() -> Self {
+        Self::new(Duration::ZERO)
+    }
+}
+
+/// 访问海报来源(豆瓣)的 client
+///
+/// 所有请求都经过同一个限速
+#[derive(Debug, Clone)]
+pub struct PosterClient {
+    transport: Arc<dyn Transport>,
+    limiter: Arc<RateLimiter>,
+    /// 每个选项比较前几个搜索结果的海报
+    candidates: usize,
+    /// 豆瓣搜索建议的链接
+    suggest_url: String,
+    /// 记录或回放请求
+    tape: Option<Arc<Tape>>,
+}
+
+impl PosterClient {
+    /// 不限速的，一般使用 `reqwest::Client`
+    pub fn new<T: Transport + 'static>(transport: T) -> Self {
+        Self {
+            transport: Arc::new(transport),
+            limiter: Arc::new(RateLimiter::default()),
+            candidates: DEFAULT_CANDIDATES,
+            suggest_url: doubanurls::SUBJECT_SUGGEST.into(),
+            tape: None,
+        }
+    }
+
+    /// 替换发送请求的 transport
+    pub fn set_transport<T: Transport + 'static>(&mut self, transport: T) {
+        self.transport = Arc::new(transport);
+    }
+
+    /// 记录或回放请求
+    pub fn set_tape(&mut self, tape: Arc<Tape>) {
+        self.tape = Some(tape);
+    }
+
+    /// 设置豆瓣搜索建议的链接，比如测试用的服务器
+    pub fn set_suggest_url(&mut self, url: &str) {
+        self.suggest_url = url.into();
+    }
+
+    /// 豆瓣搜索建议的链接
+    pub fn suggest_url(&self) -> &str {
+        &self.suggest_url
+    }
+
+    /// 设置每个选项比较前几个搜索结果的海报，至少是 1
+    pub fn set_candidates(&mut self, candidates: usize) {
+        self.candidates = candidates.max(1);
+    }
+
+    /// 设置限速
+    pub fn set_limiter(&mut self, limiter: Arc<RateLimiter>) {
+        self.limiter = limiter;
+    }
+
+    pub fn transport(&self) -> &dyn Transport {
+        &*self.transport
+    }
+
+    /// 限速后发送 GET 请求
+    pub async fn get<Q>(&self, url: &str, query: &Q) -> Result<Response>
+    where
+        Q: Serialize + ?Sized,
+    {
+        self.limiter.wait().await;
+        let req = transport::get_with_query(url, query)?;
+        tape::send(self.tape.as_deref(), &*self.transport, req).await
+    }
+}
+
+/// 默认的相似度阈值，百分制
+pub const DEFAULT_THRESHOLD: f64 = 93.0;
+
+/// 比较前统一缩放到的尺寸
+pub const NORMALIZED_SIZE: (u32, u32) = (120, 200);
+
+/// 缩放到统一尺寸时，如何处理宽高比不同的图片
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// 直接拉伸，不裁掉海报的任何部分
+    #[default]
+    Stretch,
+    /// 从中间裁剪出相同的宽高比
+    Crop,
+    /// 完整保留，空白处填充黑色
+    Letterbox,
+}
+
+impl Display for Fit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Crop => write!(f, "crop"),
+            Self::Letterbox => write!(f, "letterbox"),
+            Self::Stretch => write!(f, "stretch"),
+        }
+    }
+}
+
+/// 比较完一个选项后的回调
+type OnScored = Box<dyn Fn(&Answer) + Send + Sync>;
 
 /// 验证码
 pub struct Kaptcha {
     pub url: String,
+    /// 原始的题图
     pub img_bytes: Option<Bytes>,
+    /// 解码后的题图
+    pixels: Option<RgbImage>,
+    /// 比较时使用的算法
+    metric: Arc<dyn SimilarityMetric>,
+    fit: Fit,
+    /// 每比较一个选项调用一次
+    on_scored: Option<OnScored>,
 }
 
 impl Kaptcha {
@@ -21,66 +180,144 @@ impl Kaptcha {
         Self {
             url,
             img_bytes: None,
+            pixels: None,
+            metric: Arc::new(DssimMetric::default()),
+            fit: Fit::default(),
+            on_scored: None,
         }
     }
 
+    /// 使用已经获取的题图，比如保存在本地的
+    pub fn with_img(url: String, img: Bytes) -> Result<Self> {
+        let mut kaptcha = Self::new(url);
+        kaptcha.set_img(img)?;
+        Ok(kaptcha)
+    }
+
+    /// 使用已经获取的题图
+    pub fn set_img(&mut self, img: Bytes) -> Result<()> {
+        self.pixels = Some(decode(&img)?);
+        self.img_bytes = Some(img);
+        Ok(())
+    }
+
+    /// 设置比较时使用的算法，默认是 DSSIM
+    pub fn set_metric(&mut self, metric: Arc<dyn SimilarityMetric>) {
+        self.metric = metric;
+    }
+
+    /// 设置缩放时如何处理宽高比，默认是裁剪
+    pub fn set_fit(&mut self, fit: Fit) {
+        self.fit = fit;
+    }
+
+    /// 每比较完一个选项(包括无法比较的)调用 `f`，用于显示进度
+    pub fn set_on_scored<F>(&mut self, f: F)
+    where
+        F: Fn(&Answer) + Send + Sync + 'static,
+    {
+        self.on_scored = Some(Box::new(f));
+    }
+
     /// 获取图片，并且保存在内存里
-    async fn get_img(&mut self, client: &Client) -> Result<()> {
-        if self.img_bytes.is_none() {
-            let b = client.get(self.url.as_str()).send().await?.bytes().await?;
-            let b = reseize_pic(b)?;
-            self.img_bytes = Some(b);
+    async fn get_img(&mut self, client: &dyn Transport) -> Result<()> {
+        if self.pixels.is_none() {
+            let req = transport::get(&self.url)?;
+            let b = client.execute(req).await?.bytes().await?;
+            self.set_img(b)?;
         }
         Ok(())
     }
 
     /// 与答案相比较
+    ///
+    /// `client` 用来获取题图，`poster_client` 用来获取选项的海报
+    ///
+    /// 返回第一个相似度不低于 `limit` 的选项，之后的选项不再比较
     pub async fn compare_with_answers(
         &mut self,
         answers: &mut [Answer],
-        client: &Client,
+        client: &dyn Transport,
+        poster_client: &PosterClient,
         limit: f64,
     ) -> Result<Answer> {
-        let mut attr = Dssim::new();
-        attr.set_scales(&[100.0, 100.0]);
+        self.compare(answers, client, poster_client, Some(limit))
+            .await?;
+        match pick_answer(answers, limit) {
+            Some(answer) => {
+                log::info!(
+                    "获取答案: {} 相似度: {}%",
+                    answer.name.as_str(),
+                    answer.score.unwrap_or_default()
+                );
+                Ok(answer.clone())
+            }
+            None => Err(Error::captcha("所有比较均失败了")),
+        }
+    }
+
+    /// 计算所有选项的相似度，保存在 `Answer::score`
+    pub async fn score_answers(
+        &mut self,
+        answers: &mut [Answer],
+        client: &dyn Transport,
+        poster_client: &PosterClient,
+    ) -> Result<()> {
+        self.compare(answers, client, poster_client, None).await
+    }
+
+    /// 依次计算选项的相似度，如果不低于 `stop_at` 则停止
+    async fn compare(
+        &mut self,
+        answers: &mut [Answer],
+        client: &dyn Transport,
+        poster_client: &PosterClient,
+        stop_at: Option<f64>,
+    ) -> Result<()> {
         self.get_img(client).await?;
-        // log::debug!("设置的阈值: {}", limit);
-        let Some(ref ori) = self.img_bytes else {
-            return Err(anyhow!("无法获取题图"));
-        };
-        let Ok(orig) = load_img(&attr, ori) else {
-            return Err(anyhow!("无法获取题图的ssimimg"));
+        let Some(ref ori) = self.pixels else {
+            return Err(Error::captcha("无法获取题图"));
         };
+        let ori = normalize(ori, self.fit);
 
         // TODO 在这里改成异步，或者多线程
         // 应该能减少很多时间
         for i in answers.iter_mut() {
-            if let Err(e) = i.get_img(client).await {
-                log::warn!("无法获取海报: {}, Err: {}", i.name, e);
-                continue;
-            } else {
-                let Some(ref pic) = i.img_bytes else {
-                    log::debug!("无法获取选项图的img");
+            let score = match i
+                .match_poster(&ori, poster_client, &*self.metric, self.fit)
+                .await
+            {
+                Ok(score) => score,
+                Err(e) => {
+                    log::warn!("无法比较选项: {}, Err: {}", i.name, e);
+                    i.error = Some(e.report().to_string());
+                    if let Some(ref f) = self.on_scored {
+                        f(i);
+                    }
                     continue;
-                };
-                let Ok(modif) = &load_img(&attr,pic) else {
-                    log::debug!("无法获取选项图的img");
-                    continue;
-                };
-                let (score, _) = attr.compare(&orig, modif);
-
-                let score = dssim_to_percent(score.into());
-                if score >= limit {
-                    log::info!("获取答案: {} 相似度: {}%", i.name.as_str(), score);
-                    return Ok(i.clone());
                 }
+            };
+
+            i.score = Some(score);
+            if let Some(ref f) = self.on_scored {
+                f(i);
+            }
+            if stop_at.is_some_and(|limit| score >= limit) {
+                break;
             }
         }
 
-        Err(anyhow!("所有比较均失败了"))
+        Ok(())
     }
 }
 
+/// 第一个相似度不低于 `limit` 的选项
+pub fn pick_answer(answers: &[Answer], limit: f64) -> Option<&Answer> {
+    answers
+        .iter()
+        .find(|a| a.score.is_some_and(|score| score >= limit))
+}
+
 impl From<String> for Kaptcha {
     fn from(value: String) -> Self {
         Self::new(value)
@@ -92,7 +329,14 @@ pub struct Answer {
     pub name: String,
     pub value: String,
     pub img_url: Option<String>,
+    /// 原始的海报
     pub img_bytes: Option<Bytes>,
+    /// 解码后的海报
+    pixels: Option<RgbImage>,
+    /// 与题图的相似度，百分制，没有比较过则是 `None`
+    pub score: Option<f64>,
+    /// 无法比较的原因
+    pub error: Option<String>,
 }
 
 impl Answer {
@@ -102,22 +346,75 @@ impl Answer {
             value,
             img_url: None,
             img_bytes: None,
+            pixels: None,
+            score: None,
+            error: None,
         }
     }
 
-    pub async fn get_img(&mut self, client: &Client) -> Result<()> {
-        let data = get_douban_data(&self.name, client).await?;
-        // log::debug!("获取到的豆瓣信息: {}", data);
-        self.img_url = Some(data.img);
-        let Some(ref url) = self.img_url else {
-            return Err(anyhow!("无法获取图片"));
-        };
-        let b = client.get(url).send().await?.bytes().await?;
+    /// 使用已经获取的海报，比如缓存在本地的
+    pub fn set_img(&mut self, img: Bytes) -> Result<()> {
+        self.pixels = Some(decode(&img)?);
+        self.img_bytes = Some(img);
+        Ok(())
+    }
 
-        let b = reseize_pic(b)?;
+    /// 与缩放后的题图 `ori` 比较，返回相似度
+    ///
+    /// 已经有海报时直接比较，否则比较豆瓣前几个搜索结果的海报，保留最相似的
+    async fn match_poster(
+        &mut self,
+        ori: &RgbImage,
+        client: &PosterClient,
+        metric: &dyn SimilarityMetric,
+        fit: Fit,
+    ) -> Result<f64> {
+        if let Some(ref pic) = self.pixels {
+            return metric.similarity(ori, &normalize(pic, fit));
+        }
 
-        self.img_bytes = Some(b);
-        Ok(())
+        let mut best: Option<(f64, String, Bytes, RgbImage)> = None;
+        for data in douban::search(&self.name, client, client.candidates).await? {
+            log::debug!("比较海报: {} {}", self.name, data);
+            let img = match client.get(&data.img, &()).await {
+                Ok(r) => match r.bytes().await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        log::debug!("无法读取海报: {}, Err: {}", data.img, e);
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    log::debug!("无法获取海报: {}, Err: {}", data.img, e);
+                    continue;
+                }
+            };
+            let pixels = match decode(&img) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::debug!("无法解码海报: {}, Err: {}", data.img, e);
+                    continue;
+                }
+            };
+            let score = match metric.similarity(ori, &normalize(&pixels, fit)) {
+                Ok(s) => s,
+                Err(e) => {
+                    log::debug!("无法比较海报: {}, Err: {}", data.img, e);
+                    continue;
+                }
+            };
+            if best.as_ref().map_or(true, |b| score > b.0) {
+                best = Some((score, data.img, img, pixels));
+            }
+        }
+
+        let Some((score, url, img, pixels)) = best else {
+            return Err(Error::captcha("无法获取图片"));
+        };
+        self.img_url = Some(url);
+        self.img_bytes = Some(img);
+        self.pixels = Some(pixels);
+        Ok(score)
     }
 }
 
@@ -127,100 +424,105 @@ impl From<(String, String)> for Answer {
     }
 }
 
-#[derive(Deserialize)]
-struct DouBanData {
-    /// 图片链接
-    img: String,
-    title: String,
-    sub_title: Option<String>,
-}
-
-impl Display for DouBanData {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match &self.sub_title {
-            None => write!(f, "DoubanData[{}]", self.title),
-            Some(subtitle) => write!(f, "DoubanData[{}-{}]", self.title, subtitle),
-        }
+/// 图片的格式，只支持 JPEG、PNG 及 WebP
+pub fn sniff_format(img: &[u8]) -> Result<ImageFormat> {
+    match image::guess_format(img)? {
+        f @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP) => Ok(f),
+        f => Err(Error::parse(format!("不支持的图片格式: {:?}", f))),
     }
 }
 
-async fn get_douban_data(name: &str, client: &Client) -> Result<DouBanData> {
-    let res: Vec<DouBanData> = client
-        .get("https://movie.douban.com/j/subject_suggest")
-        .query(&[("q", name)])
-        .send()
-        .await?
-        .json()
-        .await?;
-
-    // log::debug!("豆瓣数据: {}个", res.len());
-
-    if let Some(d) = res.into_iter().next() {
-        Ok(d)
-    } else {
-        Err(anyhow!("无法获取豆瓣数据"))
-    }
+/// 图片的扩展名，无法识别时使用 `bin`
+pub fn extension(img: &[u8]) -> &'static str {
+    sniff_format(img)
+        .map(|f| f.extensions_str()[0])
+        .unwrap_or("bin")
 }
 
-fn load_img(attr: &Dssim, m_b: &Bytes) -> Result<DssimImage<f32>> {
-    let img = load_image::load_data(m_b)?;
+/// 识别格式并解码
+pub fn decode(img: &[u8]) -> Result<RgbImage> {
+    let format = sniff_format(img)?;
+    Ok(image::load_from_memory_with_format(img, format)?.to_rgb8())
+}
 
-    let res = match img.bitmap {
-        ImageData::RGB8(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgblu(), img.width, img.height))
-        }
-        ImageData::RGB16(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgblu(), img.width, img.height))
-        }
-        ImageData::RGBA8(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgbaplu(), img.width, img.height))
-        }
-        ImageData::RGBA16(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgbaplu(), img.width, img.height))
-        }
-        ImageData::GRAY8(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgblu(), img.width, img.height))
-        }
-        ImageData::GRAY16(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgblu(), img.width, img.height))
-        }
-        ImageData::GRAYA8(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgbaplu(), img.width, img.height))
+/// 使用 Catmull-Rom 插值缩放到 [`NORMALIZED_SIZE`]
+pub fn normalize(img: &RgbImage, fit: Fit) -> RgbImage {
+    let (w, h) = NORMALIZED_SIZE;
+    let (iw, ih) = img.dimensions();
+    let filter = FilterType::CatmullRom;
+    match fit {
+        Fit::Stretch => image::imageops::resize(img, w, h, filter),
+        Fit::Crop => {
+            // 与目标宽高比相同的最大区域
+            let (cw, ch) = if iw as u64 * h as u64 > ih as u64 * w as u64 {
+                ((ih as u64 * w as u64 / h as u64).max(1) as u32, ih)
+            } else {
+                (iw, (iw as u64 * h as u64 / w as u64).max(1) as u32)
+            };
+            let cropped =
+                image::imageops::crop_imm(img, (iw - cw) / 2, (ih - ch) / 2, cw, ch).to_image();
+            image::imageops::resize(&cropped, w, h, filter)
         }
-        ImageData::GRAYA16(ref bitmap) => {
-            attr.create_image(&Img::new(bitmap.to_rgbaplu(), img.width, img.height))
+        Fit::Letterbox => {
+            let scale = f64::min(w as f64 / iw as f64, h as f64 / ih as f64);
+            let sw = ((iw as f64 * scale).round() as u32).clamp(1, w);
+            let sh = ((ih as f64 * scale).round() as u32).clamp(1, h);
+            let scaled = image::imageops::resize(img, sw, sh, filter);
+            let mut canvas = RgbImage::new(w, h);
+            image::imageops::replace(
+                &mut canvas,
+                &scaled,
+                ((w - sw) / 2).into(),
+                ((h - sh) / 2).into(),
+            );
+            canvas
         }
-    };
-    match res {
-        None => Err(anyhow!("error!!!")),
-        Some(i) => Ok(i),
     }
 }
 
-/// 设置图片尺寸
-fn reseize_pic(pic1: Bytes) -> Result<Bytes> {
-    let mut reader = image::io::Reader::new(Cursor::new(pic1));
-    reader.set_format(image::ImageFormat::Jpeg);
-    let img = reader.decode()?;
-
-    let img = image::imageops::resize(&img, 120, 200, image::imageops::FilterType::Nearest);
-
-    let mut buf = Vec::new();
-    img.write_to(&mut Cursor::new(&mut buf), ImageFormat::Jpeg)?;
-
-    Ok(buf.into())
-}
-
-// 将DSSIM结果转换为百分制，越高越相似
-fn dssim_to_percent(dssim: f64) -> f64 {
-    (1.0 - 2.0 * dssim.atan() / std::f64::consts::PI) * 100.0
-}
-
 #[cfg(test)]
 mod pic_test {
     use super::*;
     #[test]
-    fn percen_test() {
-        assert!(dssim_to_percent(0.1) >= 93.0);
+    fn pick_test() {
+        let answer = |name: &str, score| {
+            let mut a = Answer::new(name.into(), name.into());
+            a.score = score;
+            a
+        };
+        let answers = [
+            answer("a", None),
+            answer("b", Some(80.0)),
+            answer("c", Some(95.0)),
+            answer("d", Some(99.0)),
+        ];
+        assert_eq!(pick_answer(&answers, 93.0).unwrap().name, "c");
+        assert!(pick_answer(&answers, 99.5).is_none());
+    }
+
+    #[test]
+    fn normalize_test() {
+        // 宽图，左右是黑色，中间是白色
+        let img = RgbImage::from_fn(300, 200, |x, _| {
+            if (60..240).contains(&x) {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        for fit in [Fit::Crop, Fit::Letterbox, Fit::Stretch] {
+            assert_eq!(normalize(&img, fit).dimensions(), NORMALIZED_SIZE);
+        }
+        // 裁剪后只剩中间的白色
+        assert_eq!(normalize(&img, Fit::Crop).get_pixel(0, 100).0, [255; 3]);
+        // 上下有黑边
+        assert_eq!(normalize(&img, Fit::Letterbox).get_pixel(60, 0).0, [0; 3]);
+
+        let mut png = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert_eq!(sniff_format(&png).unwrap(), ImageFormat::Png);
+        assert_eq!(decode(&png).unwrap().dimensions(), (300, 200));
+        assert!(decode(b"GIF89a").is_err());
     }
 }
@@ -0,0 +1,96 @@
+//! 签到过程中的事件
+//!
+//! `TjuPtUser` 签到时依次发出 [`Event`]，通过回调或者 channel 接收，
+//! 可以用来显示进度条或者转发到其他地方
+
+use serde::Serialize;
+use std::{fmt::Debug, sync::Arc};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// 签到的一个阶段
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// 批量签到时开始此用户，之前在等待并发限制或开始的间隔
+    Started,
+    /// 开始第几次尝试，共 `retry` 次
+    AttemptStarted { attempt: u8, retry: u8 },
+    /// 开始获取签到页面，cookie 无效时登录
+    LoginStarted,
+    /// 已经获取签到页面
+    LoginSucceeded,
+    /// 解析出题图及选项
+    PageParsed { options: usize },
+    /// 比较了一个选项，无法比较时 `score` 为 `None`
+    OptionScored {
+        name: String,
+        score: Option<f64>,
+        error: Option<String>,
+    },
+    /// 已经提交答案，`accepted` 为站点是否接受，不接受时之后会重试或失败
+    AnswerSubmitted {
+        answer: String,
+        score: Option<f64>,
+        accepted: bool,
+    },
+    /// 第几次尝试失败，之后还会重试
+    RetryScheduled { attempt: u8, error: String },
+    /// 签到结束，成功时有 `answer`，失败时有 `error`
+    Finished {
+        answer: Option<String>,
+        attempts: u8,
+        error: Option<String>,
+    },
+}
+
+/// 一个用户的事件
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ProgressEvent {
+    pub user: String,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+type Callback = dyn Fn(ProgressEvent) + Send + Sync;
+
+/// 接收事件，可以在多个用户之间共享
+#[derive(Clone)]
+pub struct Progress {
+    callback: Arc<Callback>,
+}
+
+impl Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Progress").finish_non_exhaustive()
+    }
+}
+
+impl Progress {
+    /// 每个事件调用一次 `callback`，在签到的线程中调用，不应该阻塞
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(ProgressEvent) + Send + Sync + 'static,
+    {
+        Self {
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// 通过 channel 接收事件，所有 `Progress` 销毁后 channel 关闭
+    pub fn channel() -> (Self, UnboundedReceiver<ProgressEvent>) {
+        let (tx, rx) = unbounded_channel();
+        let progress = Self::new(move |e| {
+            // 接收端关闭了就不再发送
+            let _res = tx.send(e);
+        });
+        (progress, rx)
+    }
+
+    /// 发出 `user` 的一个事件
+    pub fn emit(&self, user: &str, event: Event) {
+        (self.callback)(ProgressEvent {
+            user: user.to_owned(),
+            event,
+        })
+    }
+}
@@ -0,0 +1,262 @@
+//! 签到结果汇总及退出码
+
+use crate::error::{Error, ErrorKind, Result};
+use serde::Serialize;
+use std::{fmt::Display, time::Duration};
+
+/// 进程退出状态
+///
+/// 退出码 2 为命令行参数错误，由 `clap` 使用
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    /// 全部成功，或者没有需要签到的用户
+    Success = 0,
+    /// 其他错误
+    Error = 1,
+    /// 部分用户失败
+    Partial = 3,
+    /// 全部用户失败
+    AllFailed = 4,
+    /// 配置文件错误
+    ConfigError = 5,
+    /// 网络不可用，全部用户都因为网络错误失败
+    NetworkDown = 6,
+}
+
+impl RunStatus {
+    /// 进程退出码
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+/// 单个用户的签到结果
+#[derive(Serialize, Debug, Clone)]
+pub struct UserOutcome {
+    pub user: String,
+    pub success: bool,
+    /// 提交的答案
+    pub answer: Option<String>,
+    /// 尝试的次数
+    pub attempts: u8,
+    /// 耗时，单位毫秒
+    pub duration_ms: u128,
+    /// 失败原因
+    pub error: Option<String>,
+    /// 是否因为网络错误失败
+    pub network_error: bool,
+    /// 每个选项的相似度，只有 `--dry-run` 时才有
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scores: Vec<OptionScore>,
+}
+
+impl UserOutcome {
+    /// 从签到结果生成
+    pub fn new(
+        user: &str,
+        result: &Result<Attended>,
+        max_attempts: u8,
+        duration: Duration,
+    ) -> Self {
+        match result {
+            Ok(attended) => Self {
+                user: user.into(),
+                success: true,
+                answer: Some(attended.answer.clone()),
+                attempts: attended.attempts,
+                duration_ms: duration.as_millis(),
+                error: None,
+                network_error: false,
+                scores: vec![],
+            },
+            Err(e) => Self {
+                user: user.into(),
+                success: false,
+                answer: None,
+                attempts: max_attempts,
+                duration_ms: duration.as_millis(),
+                error: Some(e.report().to_string()),
+                network_error: is_network_error(e),
+                scores: vec![],
+            },
+        }
+    }
+
+    /// 签到的任务异常退出(panic)，算作失败
+    pub fn panicked(user: &str, max_attempts: u8, duration: Duration, reason: &str) -> Self {
+        Self {
+            user: user.into(),
+            success: false,
+            answer: None,
+            attempts: max_attempts,
+            duration_ms: duration.as_millis(),
+            error: Some(format!("签到异常退出: {}", reason)),
+            network_error: false,
+            scores: vec![],
+        }
+    }
+
+    /// 加上每个选项的相似度
+    pub fn with_scores(mut self, scores: Vec<OptionScore>) -> Self {
+        self.scores = scores;
+        self
+    }
+}
+
+/// 选项及其相似度
+#[derive(Serialize, Debug, Clone)]
+pub struct OptionScore {
+    pub name: String,
+    /// 百分制，无法比较则是 `None`
+    pub score: Option<f64>,
+}
+
+impl Display for OptionScore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.score {
+            Some(score) => write!(f, "{}: {:.2}%", self.name, score),
+            None => write!(f, "{}: 无法比较", self.name),
+        }
+    }
+}
+
+/// 签到成功的信息
+#[derive(Debug, Clone)]
+pub struct Attended {
+    /// 提交的答案
+    pub answer: String,
+    /// 第几次尝试成功的
+    pub attempts: u8,
+}
+
+/// 一次运行的汇总
+#[derive(Serialize, Debug, Clone)]
+pub struct RunSummary {
+    pub status: RunStatus,
+    /// 没有运行签到时的错误，比如配置文件错误
+    pub error: Option<String>,
+    pub outcomes: Vec<UserOutcome>,
+}
+
+impl RunSummary {
+    /// 根据每个用户的结果计算状态
+    pub fn new(outcomes: Vec<UserOutcome>) -> Self {
+        let failed = outcomes.iter().filter(|o| !o.success).count();
+        let status = if failed == 0 {
+            RunStatus::Success
+        } else if failed < outcomes.len() {
+            RunStatus::Partial
+        } else if outcomes.iter().all(|o| o.network_error) {
+            RunStatus::NetworkDown
+        } else {
+            RunStatus::AllFailed
+        };
+        Self {
+            status,
+            error: None,
+            outcomes,
+        }
+    }
+
+    /// 不是签到，比如 `doctor`，只有状态
+    pub fn with_status(status: RunStatus) -> Self {
+        Self {
+            status,
+            error: None,
+            outcomes: vec![],
+        }
+    }
+
+    /// 没有运行签到就失败了
+    pub fn failed(status: RunStatus, error: &Error) -> Self {
+        Self {
+            status,
+            error: Some(error.report().to_string()),
+            outcomes: vec![],
+        }
+    }
+}
+
+impl Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let succeeded = self.outcomes.iter().filter(|o| o.success).count();
+        write!(
+            f,
+            "RunSummary[{:?} {}/{}]",
+            self.status,
+            succeeded,
+            self.outcomes.len()
+        )
+    }
+}
+
+/// 根据错误判断退出状态
+pub fn error_status(e: &Error) -> RunStatus {
+    if e.kind() == ErrorKind::Config {
+        RunStatus::ConfigError
+    } else if is_network_error(e) {
+        RunStatus::NetworkDown
+    } else {
+        RunStatus::Error
+    }
+}
+
+/// 是否是网络错误，比如无法连接、超时
+pub fn is_network_error(e: &Error) -> bool {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(e);
+    while let Some(e) = source {
+        // 直接转换的 `reqwest::Error` 是透明的，不在来源中
+        let http = match e.downcast_ref::<Error>() {
+            Some(Error::Http(e)) => Some(e),
+            _ => e.downcast_ref::<reqwest::Error>(),
+        };
+        if http.is_some_and(|e| e.is_connect() || e.is_timeout()) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod report_test {
+    use super::*;
+
+    fn outcome(success: bool, network_error: bool) -> UserOutcome {
+        UserOutcome {
+            user: "user".into(),
+            success,
+            answer: None,
+            attempts: 1,
+            duration_ms: 0,
+            error: None,
+            network_error,
+            scores: vec![],
+        }
+    }
+
+    #[test]
+    fn status_test() {
+        let status = |o: Vec<UserOutcome>| RunSummary::new(o).status;
+        assert_eq!(status(vec![]), RunStatus::Success);
+        assert_eq!(status(vec![outcome(true, false)]), RunStatus::Success);
+        assert_eq!(
+            status(vec![outcome(true, false), outcome(false, false)]),
+            RunStatus::Partial
+        );
+        assert_eq!(
+            status(vec![outcome(false, true), outcome(false, false)]),
+            RunStatus::AllFailed
+        );
+        assert_eq!(
+            status(vec![outcome(false, true), outcome(false, true)]),
+            RunStatus::NetworkDown
+        );
+        let panicked = UserOutcome::panicked("user", 1, Duration::ZERO, "boom");
+        assert_eq!(
+            status(vec![outcome(true, false), panicked]),
+            RunStatus::Partial
+        );
+    }
+}
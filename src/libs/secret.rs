@@ -0,0 +1,165 @@
+//! 敏感信息，比如密码
+//!
+//! `Debug` 及 `Display` 只会输出 `***`，销毁时清零
+
+use crate::error::{Context, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, fs::read_to_string, process::Command};
+use zeroize::Zeroize;
+
+/// 敏感字符串
+///
+/// 序列化时是原本的字符串，保证配置文件可以正常读写
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(s: String) -> Self {
+        Self(s)
+    }
+
+    /// 获取明文，注意不要输出到日志
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// 解析外部来源
+    ///
+    /// - `env:NAME`: 读取环境变量 `NAME`
+    /// - `file:PATH`: 读取文件内容，去掉末尾的换行
+    /// - `cmd:COMMAND`: 通过 shell 执行命令，取标准输出，去掉末尾的换行
+    /// - 其他: 原样返回
+    pub fn resolve(&self) -> Result<Secret> {
+        let raw = self.expose();
+        if let Some(name) = raw.strip_prefix("env:") {
+            let value = std::env::var(name).context(format!("无法读取环境变量: {}", name))?;
+            Ok(Self(value))
+        } else if let Some(path) = raw.strip_prefix("file:") {
+            let content = read_to_string(path)
+                .context_as(Error::config, format!("无法读取密码文件: {}", path))?;
+            Ok(Self(content).trim_newline())
+        } else if let Some(cmd) = raw.strip_prefix("cmd:") {
+            let mut output = if cfg!(target_os = "windows") {
+                Command::new("cmd").args(["/C", cmd]).output()
+            } else {
+                Command::new("sh").args(["-c", cmd]).output()
+            }
+            .context_as(Error::config, format!("无法执行命令: {}", cmd))?;
+
+            if !output.status.success() {
+                output.stdout.zeroize();
+                return Err(Error::config(format!(
+                    "命令执行失败: {}, {}",
+                    cmd, output.status
+                )));
+            }
+            let content = String::from_utf8(output.stdout).map_err(|e| {
+                let mut bytes = e.into_bytes();
+                bytes.zeroize();
+                Error::config(format!("命令输出不是UTF-8: {}", cmd))
+            })?;
+            Ok(Self(content).trim_newline())
+        } else {
+            Ok(self.clone())
+        }
+    }
+
+    /// 去掉末尾的换行
+    fn trim_newline(mut self) -> Self {
+        while self.0.ends_with(['\n', '\r']) {
+            let _r = self.0.pop();
+        }
+        self
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value.into())
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+/// 替换 `secrets` 及 passkey 为 `***`，用于保存到文件的响应
+pub fn redact(s: &str, secrets: &[&str]) -> String {
+    let mut s = s.to_owned();
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        s = s.replace(secret, "***");
+    }
+
+    const PASSKEY: &str = "passkey=";
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s.as_str();
+    while let Some(pos) = rest.find(PASSKEY) {
+        let (head, tail) = rest.split_at(pos + PASSKEY.len());
+        out.push_str(head);
+        let end = tail
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(tail.len());
+        if end > 0 {
+            out.push_str("***");
+        }
+        rest = &tail[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod secret_test {
+    use super::*;
+
+    #[test]
+    fn resolve_test() {
+        std::env::set_var("TJUPTATT_TEST_SECRET", "from_env");
+        let resolve = |s: &str| Secret::from(s).resolve();
+        assert_eq!(
+            resolve("env:TJUPTATT_TEST_SECRET").unwrap().expose(),
+            "from_env"
+        );
+        assert_eq!(resolve("plain").unwrap().expose(), "plain");
+        assert!(resolve("env:TJUPTATT_TEST_SECRET_NOT_EXIST").is_err());
+        #[cfg(unix)]
+        assert_eq!(resolve("cmd:echo from_cmd").unwrap().expose(), "from_cmd");
+    }
+
+    #[test]
+    fn redact_test() {
+        let secret = Secret::from("pwd");
+        assert_eq!(format!("{:?}", secret), "***");
+        assert_eq!(format!("{}", secret), "***");
+
+        assert_eq!(
+            redact(
+                r#"pwd: hunter2 <a href="torrentrss.php?passkey=abc123&x=1">"#,
+                &["hunter2", ""]
+            ),
+            r#"pwd: *** <a href="torrentrss.php?passkey=***&x=1">"#
+        );
+        assert_eq!(redact("passkey=", &[]), "passkey=");
+    }
+}
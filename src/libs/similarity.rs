@@ -0,0 +1,358 @@
+//! 图片相似度算法
+//!
+//! 所有算法都返回百分制的相似度，越高越相似
+
+use crate::error::{Error, Result};
+use dssim::{Dssim, DssimImage};
+use image::{imageops::FilterType, GrayImage, RgbImage};
+use rgb::RGB8;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Display, str::FromStr, sync::Arc};
+
+/// 默认的 DSSIM scales
+pub const DEFAULT_SCALES: [f64; 2] = [100.0, 100.0];
+
+/// 颜色直方图每个通道的分组数
+const HISTOGRAM_BINS: usize = 4;
+
+/// 无关图片的典型相似度，DSSIM 约为 0.5
+const DSSIM_BASELINE: f64 = 70.0;
+/// 无关图片的哈希约有一半的位相同
+const HASH_BASELINE: f64 = 50.0;
+/// 无关海报的颜色直方图也有较多重叠
+const HISTOGRAM_BASELINE: f64 = 40.0;
+
+/// 图片相似度算法
+///
+/// 比较的图片已经缩放到相同的尺寸，见 [`crate::picparser::normalize`]
+pub trait SimilarityMetric: Send + Sync {
+    /// 两张图片的相似度，百分制
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64>;
+
+    /// 无关图片的典型相似度，[`Ensemble`] 据此把各算法校准到同一尺度
+    fn baseline(&self) -> f64 {
+        0.0
+    }
+}
+
+/// 算法的种类
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    Dssim,
+    /// 基于 DCT 的感知哈希
+    Phash,
+    /// 基于相邻像素差的哈希
+    Dhash,
+    /// 颜色直方图
+    Histogram,
+    /// 按权重组合其他算法
+    Ensemble,
+}
+
+impl Display for MetricKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Dssim => write!(f, "dssim"),
+            Self::Phash => write!(f, "phash"),
+            Self::Dhash => write!(f, "dhash"),
+            Self::Histogram => write!(f, "histogram"),
+            Self::Ensemble => write!(f, "ensemble"),
+        }
+    }
+}
+
+impl FromStr for MetricKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "dssim" => Ok(Self::Dssim),
+            "phash" => Ok(Self::Phash),
+            "dhash" => Ok(Self::Dhash),
+            "histogram" => Ok(Self::Histogram),
+            "ensemble" => Ok(Self::Ensemble),
+            _ => Err(Error::config(format!("不支持的相似度算法: {}", s))),
+        }
+    }
+}
+
+/// 生成算法
+///
+/// `weights` 只在 `Ensemble` 时使用，为空则所有算法的权重相同，`scales` 只在使用 DSSIM 时使用
+pub fn build(
+    kind: MetricKind,
+    weights: &BTreeMap<MetricKind, f64>,
+    scales: &[f64],
+) -> Arc<dyn SimilarityMetric> {
+    match kind {
+        MetricKind::Dssim => Arc::new(DssimMetric::new(scales)),
+        MetricKind::Phash => Arc::new(PHash),
+        MetricKind::Dhash => Arc::new(DHash),
+        MetricKind::Histogram => Arc::new(Histogram),
+        MetricKind::Ensemble => {
+            let all = [
+                MetricKind::Dssim,
+                MetricKind::Phash,
+                MetricKind::Dhash,
+                MetricKind::Histogram,
+            ];
+            let metrics = all
+                .into_iter()
+                .filter_map(|k| {
+                    let weight = if weights.is_empty() {
+                        1.0
+                    } else {
+                        weights.get(&k).copied().unwrap_or(0.0)
+                    };
+                    (weight > 0.0).then(|| (build(k, weights, scales), weight))
+                })
+                .collect();
+            Arc::new(Ensemble::new(metrics))
+        }
+    }
+}
+
+/// DSSIM
+pub struct DssimMetric {
+    scales: Vec<f64>,
+}
+
+impl DssimMetric {
+    pub fn new(scales: &[f64]) -> Self {
+        Self {
+            scales: scales.to_vec(),
+        }
+    }
+}
+
+impl Default for DssimMetric {
+    fn default() -> Self {
+        Self::new(&DEFAULT_SCALES)
+    }
+}
+
+impl SimilarityMetric for DssimMetric {
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64> {
+        let mut attr = Dssim::new();
+        attr.set_scales(&self.scales);
+        let a = load_img(&attr, a)?;
+        let b = load_img(&attr, b)?;
+        let (score, _) = attr.compare(&a, b);
+        Ok(dssim_to_percent(score.into()))
+    }
+
+    fn baseline(&self) -> f64 {
+        DSSIM_BASELINE
+    }
+}
+
+/// 感知哈希，对重新压缩、轻微的水印不敏感
+pub struct PHash;
+
+impl SimilarityMetric for PHash {
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64> {
+        Ok(hash_similarity(
+            phash(&gray(a, 32, 32)),
+            phash(&gray(b, 32, 32)),
+            63,
+        ))
+    }
+
+    fn baseline(&self) -> f64 {
+        HASH_BASELINE
+    }
+}
+
+/// 差值哈希
+pub struct DHash;
+
+impl SimilarityMetric for DHash {
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64> {
+        Ok(hash_similarity(
+            dhash(&gray(a, 9, 8)),
+            dhash(&gray(b, 9, 8)),
+            64,
+        ))
+    }
+
+    fn baseline(&self) -> f64 {
+        HASH_BASELINE
+    }
+}
+
+/// 颜色直方图的交集，对裁剪不敏感
+pub struct Histogram;
+
+impl SimilarityMetric for Histogram {
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64> {
+        let (a, b) = (histogram(a), histogram(b));
+        Ok(a.iter().zip(b.iter()).map(|(x, y)| x.min(*y)).sum::<f64>() * 100.0)
+    }
+
+    fn baseline(&self) -> f64 {
+        HISTOGRAM_BASELINE
+    }
+}
+
+/// 按权重组合其他算法
+///
+/// 各算法的相似度分布不同，先按 [`SimilarityMetric::baseline`] 把无关图片映射到 0、
+/// 相同图片映射到 1，加权平均后再映射回 DSSIM 的尺度，使同一个阈值对单独的 DSSIM 及组合都适用
+pub struct Ensemble {
+    metrics: Vec<(Arc<dyn SimilarityMetric>, f64)>,
+}
+
+impl Ensemble {
+    pub fn new(metrics: Vec<(Arc<dyn SimilarityMetric>, f64)>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl SimilarityMetric for Ensemble {
+    fn similarity(&self, a: &RgbImage, b: &RgbImage) -> Result<f64> {
+        let total: f64 = self.metrics.iter().map(|(_, w)| w).sum();
+        if total <= 0.0 {
+            return Err(Error::config("没有可用的算法"));
+        }
+        let mut score = 0.0;
+        for (metric, weight) in self.metrics.iter() {
+            score += calibrate(metric.similarity(a, b)?, metric.baseline()) * weight;
+        }
+        Ok(DSSIM_BASELINE + score / total * (100.0 - DSSIM_BASELINE))
+    }
+
+    fn baseline(&self) -> f64 {
+        DSSIM_BASELINE
+    }
+}
+
+/// 把 `baseline` ~ 100 的相似度映射到 0 ~ 1，低于 `baseline` 的都是 0
+fn calibrate(score: f64, baseline: f64) -> f64 {
+    ((score - baseline) / (100.0 - baseline)).clamp(0.0, 1.0)
+}
+
+fn gray(img: &RgbImage, w: u32, h: u32) -> GrayImage {
+    let img = image::imageops::grayscale(img);
+    image::imageops::resize(&img, w, h, FilterType::Triangle)
+}
+
+/// 32x32 灰度图 DCT 的左上角 8x8，不包括直流分量的 63 个系数与中位数比较
+fn phash(img: &GrayImage) -> u64 {
+    let n = img.width() as usize;
+    let pixels = img.pixels().map(|p| p.0[0] as f64).collect::<Vec<_>>();
+    let basis = |k: usize, i: usize| {
+        (std::f64::consts::PI * (2 * i + 1) as f64 * k as f64 / (2 * n) as f64).cos()
+    };
+
+    let mut coefs = Vec::with_capacity(64);
+    for u in 0..8 {
+        for v in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..n {
+                for x in 0..n {
+                    sum += pixels[y * n + x] * basis(u, y) * basis(v, x);
+                }
+            }
+            coefs.push(sum);
+        }
+    }
+
+    // 直流分量只反映整体亮度，不参与比较
+    let coefs = &coefs[1..];
+    let mut sorted = coefs.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coefs.iter().enumerate().fold(
+        0,
+        |acc, (i, &c)| if c > median { acc | 1 << i } else { acc },
+    )
+}
+
+/// 9x8 灰度图每行相邻像素比较
+fn dhash(img: &GrayImage) -> u64 {
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let bit = y * 8 + x;
+            if img.get_pixel(x, y).0[0] > img.get_pixel(x + 1, y).0[0] {
+                hash |= 1 << bit;
+            }
+        }
+    }
+    hash
+}
+
+/// 两个 `bits` 位的哈希中相同的位所占的比例
+fn hash_similarity(a: u64, b: u64, bits: u32) -> f64 {
+    (1.0 - (a ^ b).count_ones() as f64 / bits as f64) * 100.0
+}
+
+/// 归一化的颜色直方图
+fn histogram(img: &RgbImage) -> Vec<f64> {
+    let mut bins = vec![0.0; HISTOGRAM_BINS.pow(3)];
+    let q = |c: u8| c as usize * HISTOGRAM_BINS / 256;
+    for p in img.pixels() {
+        let [r, g, b] = p.0;
+        bins[(q(r) * HISTOGRAM_BINS + q(g)) * HISTOGRAM_BINS + q(b)] += 1.0;
+    }
+    let total = (img.width() * img.height()).max(1) as f64;
+    bins.iter_mut().for_each(|b| *b /= total);
+    bins
+}
+
+fn load_img(attr: &Dssim, img: &RgbImage) -> Result<DssimImage<f32>> {
+    let pixels = img
+        .pixels()
+        .map(|p| RGB8::new(p.0[0], p.0[1], p.0[2]))
+        .collect::<Vec<_>>();
+    attr.create_image_rgb(&pixels, img.width() as usize, img.height() as usize)
+        .ok_or_else(|| Error::captcha("无法生成 DSSIM 图片"))
+}
+
+// 将DSSIM结果转换为百分制，越高越相似
+fn dssim_to_percent(dssim: f64) -> f64 {
+    (1.0 - 2.0 * dssim.atan() / std::f64::consts::PI) * 100.0
+}
+
+#[cfg(test)]
+mod similarity_test {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn percen_test() {
+        assert!(dssim_to_percent(0.1) >= 93.0);
+    }
+
+    #[test]
+    fn metric_test() {
+        let a = RgbImage::from_fn(60, 100, |x, y| Rgb([(x * 4) as u8, (y * 2) as u8, 128]));
+        let b = RgbImage::from_fn(60, 100, |x, _| Rgb([0, 255 - (x * 4) as u8, 0]));
+        for kind in [
+            MetricKind::Dssim,
+            MetricKind::Phash,
+            MetricKind::Dhash,
+            MetricKind::Histogram,
+            MetricKind::Ensemble,
+        ] {
+            let metric = build(kind, &BTreeMap::new(), &DEFAULT_SCALES);
+            let same = metric.similarity(&a, &a).unwrap();
+            let diff = metric.similarity(&a, &b).unwrap();
+            assert!(same > 99.0, "{} {}", kind, same);
+            assert!(diff < same, "{} {} {}", kind, diff, same);
+        }
+    }
+
+    #[test]
+    fn ensemble_test() {
+        assert_eq!(calibrate(40.0, 50.0), 0.0);
+        assert_eq!(calibrate(75.0, 50.0), 0.5);
+        // 无关的图片不会因为哈希约有一半相同而得到较高的相似度
+        let a = RgbImage::from_fn(60, 100, |x, y| Rgb([(x * 4) as u8, (y * 2) as u8, 128]));
+        let b = RgbImage::from_fn(60, 100, |x, _| Rgb([0, 255 - (x * 4) as u8, 0]));
+        let metric = build(MetricKind::Ensemble, &BTreeMap::new(), &DEFAULT_SCALES);
+        assert_eq!(metric.similarity(&a, &a).unwrap(), 100.0);
+        assert!(metric.similarity(&a, &b).unwrap() < 93.0);
+    }
+}
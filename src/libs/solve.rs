@@ -0,0 +1,177 @@
+//! 离线计算答案
+//!
+//! 使用保存在本地的题图及签到页面，不访问 tjupt，海报优先从本地缓存读取
+
+use crate::error::{Context, Result};
+use crate::picparser::{self, Answer, Kaptcha, PosterClient};
+use std::{
+    fs::{create_dir_all, read, write},
+    path::{Path, PathBuf},
+};
+
+/// 缓存的海报可能的扩展名
+const EXTENSIONS: [&str; 3] = ["jpg", "png", "webp"];
+
+/// 海报的本地缓存，以选项名保存，扩展名与海报的格式相同
+#[derive(Debug, Clone)]
+pub struct PosterCache {
+    dir: PathBuf,
+}
+
+impl PosterCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path(&self, name: &str, ext: &str) -> PathBuf {
+        let name: String = name
+            .trim()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c => c,
+            })
+            .collect();
+        self.dir.join(format!("{}.{}", name, ext))
+    }
+
+    /// 读取缓存的海报，返回是否有缓存
+    pub fn load(&self, answer: &mut Answer) -> bool {
+        let Some(img) = EXTENSIONS
+            .iter()
+            .find_map(|ext| read(self.path(&answer.name, ext)).ok())
+        else {
+            return false;
+        };
+        match answer.set_img(img.into()) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("无法读取缓存的海报: {}, Err: {}", answer.name, e.report());
+                false
+            }
+        }
+    }
+
+    /// 缓存海报
+    pub fn save(&self, answer: &Answer) -> Result<()> {
+        let Some(ref img) = answer.img_bytes else {
+            return Ok(());
+        };
+        create_dir_all(&self.dir)
+            .context(format!("无法创建海报缓存文件夹: {}", self.dir.display()))?;
+        write(self.path(&answer.name, picparser::extension(img)), img)?;
+        Ok(())
+    }
+}
+
+/// 读取本地的题图
+pub fn load_kaptcha<P: AsRef<Path>>(path: P) -> Result<Kaptcha> {
+    let path = path.as_ref();
+    let img = read(path).context(format!("无法读取题图: {}", path.display()))?;
+    Kaptcha::with_img(path.display().to_string(), img.into())
+}
+
+/// 解析 `标题=值` 格式的选项，没有 `=` 时值与标题相同
+pub fn parse_option(s: &str) -> Answer {
+    match s.rsplit_once('=') {
+        Some((name, value)) => Answer::new(name.to_string(), value.to_string()),
+        None => Answer::new(s.to_string(), s.to_string()),
+    }
+}
+
+/// 计算每个选项的相似度
+///
+/// 海报优先从 `cache` 读取，获取到的海报会写入 `cache`
+///
+/// `offline` 为 true 时不访问海报来源，没有缓存的选项不会计算
+pub async fn solve(
+    kaptcha: &mut Kaptcha,
+    answers: &mut [Answer],
+    poster_client: &PosterClient,
+    cache: &PosterCache,
+    offline: bool,
+) -> Result<()> {
+    let cached = answers
+        .iter_mut()
+        .map(|a| cache.load(a))
+        .collect::<Vec<_>>();
+
+    if offline {
+        let mut local = answers
+            .iter()
+            .zip(cached.iter())
+            .filter(|(_, &c)| c)
+            .map(|(a, _)| a.clone())
+            .collect::<Vec<_>>();
+        kaptcha
+            .score_answers(&mut local, poster_client.transport(), poster_client)
+            .await?;
+        for (a, l) in answers
+            .iter_mut()
+            .zip(cached.iter())
+            .filter(|(_, &c)| c)
+            .map(|(a, _)| a)
+            .zip(local)
+        {
+            a.score = l.score;
+        }
+    } else {
+        kaptcha
+            .score_answers(answers, poster_client.transport(), poster_client)
+            .await?;
+        for (a, _) in answers.iter().zip(cached).filter(|(_, c)| !c) {
+            if let Err(e) = cache.save(a) {
+                log::warn!("无法缓存海报: {}, Err: {}", a.name, e.report());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 按相似度从高到低排列，没有相似度的在最后
+pub fn ranking(answers: &[Answer]) -> Vec<&Answer> {
+    let mut ranked = answers.iter().collect::<Vec<_>>();
+    ranked.sort_by(|a, b| {
+        b.score
+            .unwrap_or(f64::MIN)
+            .total_cmp(&a.score.unwrap_or(f64::MIN))
+    });
+    ranked
+}
+
+#[cfg(test)]
+mod solve_test {
+    use super::*;
+
+    #[test]
+    fn option_test() {
+        let a = parse_option("a=b=1");
+        assert_eq!((a.name.as_str(), a.value.as_str()), ("a=b", "1"));
+        let a = parse_option("title");
+        assert_eq!((a.name.as_str(), a.value.as_str()), ("title", "title"));
+
+        let cache = PosterCache::new("/tmp");
+        assert_eq!(cache.path("a/b: c", "jpg"), Path::new("/tmp/a_b_ c.jpg"));
+    }
+
+    #[test]
+    fn cache_test() {
+        let dir = std::env::temp_dir().join(format!("tjuptatt_cache_{}", std::process::id()));
+        let cache = PosterCache::new(&dir);
+        let mut png = vec![];
+        image::RgbImage::new(2, 2)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let mut answer = parse_option("title");
+        answer.set_img(png.into()).unwrap();
+        cache.save(&answer).unwrap();
+        assert!(dir.join("title.png").is_file());
+
+        let mut loaded = parse_option("title");
+        assert!(cache.load(&mut loaded));
+        assert_eq!(loaded.img_bytes, answer.img_bytes);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
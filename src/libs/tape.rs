@@ -0,0 +1,276 @@
+//! 记录及回放 http 请求
+//!
+//! 记录时保存 tjupt 及海报来源的每个请求及响应，密码及 passkey 会被替换为 `***`；
+//! 回放时按顺序返回保存的响应，不访问网络，用于确定地重现问题。
+//!
+//! 每个用户一个文件夹，包含 `tape.json` 及每个响应的内容 `bodies/*`
+
+use crate::{
+    error::{Context, Error, Result},
+    secret::redact,
+    transport::Transport,
+};
+use reqwest::{
+    header::{CONTENT_TYPE, LOCATION},
+    Request, Response, ResponseBuilderExt, Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{create_dir_all, read, read_to_string, write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+const TAPE_FILE: &str = "tape.json";
+const BODY_DIR: &str = "bodies";
+
+/// 一个请求及其响应
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Entry {
+    method: String,
+    /// 请求的链接，包含 query
+    url: String,
+    /// 请求的内容，比如登录的表单
+    request_body: Option<String>,
+    status: u16,
+    /// 重定向之后的链接
+    final_url: String,
+    content_type: Option<String>,
+    location: Option<String>,
+    /// 响应的内容，相对于记录的文件夹
+    body: String,
+    /// 回放时是否已经使用
+    #[serde(skip)]
+    used: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+/// 一个用户的请求记录
+#[derive(Debug)]
+pub struct Tape {
+    dir: PathBuf,
+    mode: Mode,
+    /// 记录时需要去掉的字符串
+    secrets: Vec<String>,
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl Tape {
+    /// 记录到 `dir`，`secrets` 会被替换为 `***`
+    pub fn record<P: AsRef<Path>>(dir: P, secrets: &[&str]) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(dir.join(BODY_DIR))
+            .context(format!("无法创建记录文件夹: {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            mode: Mode::Record,
+            secrets: secrets.iter().map(|s| s.to_string()).collect(),
+            entries: Mutex::default(),
+        })
+    }
+
+    /// 从 `dir` 回放，`secrets` 与记录时相同，请求的链接替换后再查找
+    pub fn replay<P: AsRef<Path>>(dir: P, secrets: &[&str]) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let path = dir.join(TAPE_FILE);
+        let entries = read_to_string(&path).context(format!("无法读取记录: {}", path.display()))?;
+        let entries =
+            serde_json::from_str(&entries).context(format!("无法解析记录: {}", path.display()))?;
+        Ok(Self {
+            dir,
+            mode: Mode::Replay,
+            secrets: secrets.iter().map(|s| s.to_string()).collect(),
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// 发送请求
+    ///
+    /// 记录时发送后保存响应，回放时返回第一个方法及链接相同且没有使用过的响应
+    pub async fn send(&self, transport: &dyn Transport, req: Request) -> Result<Response> {
+        let method = req.method().to_string();
+        let url = req.url().to_string();
+        match self.mode {
+            // 记录的链接中的密码及 passkey 已经被替换
+            Mode::Replay => self.replay_one(&method, &self.scrub(&url)),
+            Mode::Record => {
+                let request_body = req
+                    .body()
+                    .and_then(|b| b.as_bytes())
+                    .map(|b| self.scrub_form(&String::from_utf8_lossy(b)));
+                let res = transport.execute(req).await?;
+                let status = res.status().as_u16();
+                let final_url = res.url().to_string();
+                let header = |name| {
+                    res.headers()
+                        .get(name)
+                        .and_then(|v: &reqwest::header::HeaderValue| v.to_str().ok())
+                        .map(str::to_owned)
+                };
+                let content_type = header(CONTENT_TYPE);
+                let location = header(LOCATION);
+                let body = res.bytes().await?;
+
+                let mut entry = Entry {
+                    method,
+                    url: self.scrub(&url),
+                    request_body,
+                    status,
+                    final_url: self.scrub(&final_url),
+                    content_type,
+                    location,
+                    body: String::new(),
+                    used: false,
+                };
+                // 只处理文本，图片保持原样
+                let saved = match std::str::from_utf8(&body) {
+                    Ok(text) => self.scrub(text).into_bytes(),
+                    Err(_) => body.to_vec(),
+                };
+                self.save(&mut entry, &saved)?;
+                build_response(&entry, body.to_vec())
+            }
+        }
+    }
+
+    /// 是否在回放
+    pub fn is_replay(&self) -> bool {
+        self.mode == Mode::Replay
+    }
+
+    /// 保存响应的内容，并更新 `tape.json`
+    fn save(&self, entry: &mut Entry, body: &[u8]) -> Result<()> {
+        let mut entries = self.entries.lock().expect("记录的锁已损坏");
+        entry.body = format!("{}/{:04}", BODY_DIR, entries.len());
+        write(self.dir.join(&entry.body), body)?;
+        entries.push(entry.clone());
+        write(
+            self.dir.join(TAPE_FILE),
+            serde_json::to_string_pretty(&*entries)?,
+        )?;
+        Ok(())
+    }
+
+    fn replay_one(&self, method: &str, url: &str) -> Result<Response> {
+        let mut entries = self.entries.lock().expect("记录的锁已损坏");
+        let Some(entry) = entries
+            .iter_mut()
+            .find(|e| !e.used && e.method == method && e.url == url)
+        else {
+            return Err(Error::network(format!(
+                "记录中没有此请求: {} {}",
+                method, url
+            )));
+        };
+        entry.used = true;
+        let body = read(self.dir.join(&entry.body))
+            .context(format!("无法读取记录的响应: {}", entry.body))?;
+        log::debug!("回放: {} {}", method, url);
+        build_response(entry, body)
+    }
+
+    fn scrub(&self, s: &str) -> String {
+        let secrets = self.secrets.iter().map(String::as_str).collect::<Vec<_>>();
+        redact(s, &secrets)
+    }
+
+    /// 表单中的值是编码过的
+    fn scrub_form(&self, s: &str) -> String {
+        let s = self.scrub(s);
+        let encoded = self
+            .secrets
+            .iter()
+            .map(|v| {
+                Url::parse_with_params("http://localhost/", &[("v", v)])
+                    .ok()
+                    .and_then(|u| u.query().map(|q| q.trim_start_matches("v=").to_owned()))
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        redact(&s, &encoded.iter().map(String::as_str).collect::<Vec<_>>())
+    }
+}
+
+fn build_response(entry: &Entry, body: Vec<u8>) -> Result<Response> {
+    let mut builder = http::Response::builder()
+        .status(entry.status)
+        .url(Url::parse(&entry.final_url)?);
+    if let Some(ref content_type) = entry.content_type {
+        builder = builder.header(CONTENT_TYPE, content_type);
+    }
+    if let Some(ref location) = entry.location {
+        builder = builder.header(LOCATION, location);
+    }
+    Ok(Response::from(builder.body(body)?))
+}
+
+/// 发送请求，设置了 `tape` 时记录或回放
+pub async fn send(
+    tape: Option<&Tape>,
+    transport: &dyn Transport,
+    req: Request,
+) -> Result<Response> {
+    match tape {
+        Some(tape) => tape.send(transport, req).await,
+        None => transport.execute(req).await,
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tape_test {
+    use super::*;
+    use crate::{
+        mock::{MockServer, Scenario, MOCK_PWD, MOCK_USER},
+        transport,
+    };
+    use reqwest::Client;
+
+    #[tokio::test]
+    async fn record_replay_test() {
+        let server = MockServer::start(Scenario::Success).await.unwrap();
+        let dir = std::env::temp_dir().join(format!("tjuptatt_tape_{}", std::process::id()));
+        let client = Client::new();
+        let takelogin = format!("{}/takelogin.php", server.url());
+        let login = || {
+            transport::post_form(
+                &takelogin,
+                &[
+                    ("username", MOCK_USER),
+                    ("password", MOCK_PWD),
+                    ("returnto", "attendance.php"),
+                ],
+            )
+            .unwrap()
+        };
+
+        let tape = Tape::record(&dir, &[MOCK_PWD]).unwrap();
+        let recorded = tape.send(&client, login()).await.unwrap();
+        let url = recorded.url().clone();
+        let text = recorded.text().await.unwrap();
+
+        let attendance_url = format!("{}/attendance.php?passkey=abc123", server.url());
+        let attendance = || transport::get(&attendance_url).unwrap();
+        tape.send(&client, attendance()).await.unwrap();
+        drop(server);
+
+        let saved = read_to_string(dir.join(TAPE_FILE)).unwrap();
+        assert!(!saved.contains(MOCK_PWD));
+        assert!(!saved.contains("abc123"));
+
+        let tape = Tape::replay(&dir, &[MOCK_PWD]).unwrap();
+        assert!(tape.is_replay());
+        // 链接中的 passkey 替换后才能找到记录
+        tape.send(&client, attendance()).await.unwrap();
+        let replayed = tape.send(&client, login()).await.unwrap();
+        assert_eq!(replayed.url(), &url);
+        assert_eq!(replayed.text().await.unwrap(), text);
+        // 每个记录只回放一次
+        assert!(tape.send(&client, login()).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+//! 在终端中显示图片
+//!
+//! 支持 kitty 图形协议及 sixel，都不支持时使用半块字符 `▀`
+
+use crate::error::{Error, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{imageops::FilterType, ImageFormat, RgbImage};
+use std::{collections::BTreeSet, fmt::Write, io::Cursor, str::FromStr};
+
+/// kitty 协议每段数据的最大长度
+const KITTY_CHUNK: usize = 4096;
+
+/// 显示图片的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    Sixel,
+    /// 半块字符，需要终端支持 24 位颜色
+    Blocks,
+}
+
+impl Protocol {
+    /// 根据环境变量猜测终端支持的方式
+    pub fn detect() -> Self {
+        let var = |k| std::env::var(k).unwrap_or_default();
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if std::env::var_os("KITTY_WINDOW_ID").is_some()
+            || term.contains("kitty")
+            || matches!(program.as_str(), "WezTerm" | "ghostty")
+        {
+            Self::Kitty
+        } else if term.contains("sixel")
+            || term.starts_with("foot")
+            || term.starts_with("mlterm")
+            || program == "iTerm.app"
+        {
+            Self::Sixel
+        } else {
+            Self::Blocks
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    /// `auto` 时自动检测
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::detect()),
+            "kitty" => Ok(Self::Kitty),
+            "sixel" => Ok(Self::Sixel),
+            "blocks" => Ok(Self::Blocks),
+            _ => Err(Error::config(format!("不支持的图片显示方式: {}", s))),
+        }
+    }
+}
+
+/// 生成显示图片的转义序列，宽度最多 `cols` 列
+pub fn render(img: &[u8], protocol: Protocol, cols: u32) -> Result<String> {
+    let img = image::load_from_memory(img)?.to_rgb8();
+    if img.width() == 0 || img.height() == 0 {
+        return Err(Error::parse("图片为空"));
+    }
+    match protocol {
+        Protocol::Kitty => kitty(&img, cols),
+        Protocol::Sixel => Ok(sixel(&img)),
+        Protocol::Blocks => Ok(blocks(&img, cols)),
+    }
+}
+
+fn kitty(img: &RgbImage, cols: u32) -> Result<String> {
+    let mut png = Vec::new();
+    img.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let data = STANDARD.encode(png);
+    let chunks = data.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+
+    let mut out = String::new();
+    for (n, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(n + 1 < chunks.len());
+        // base64 只有 ascii
+        let chunk = std::str::from_utf8(chunk)?;
+        if n == 0 {
+            let _r = write!(out, "\x1b_Ga=T,f=100,c={},m={};{}\x1b\\", cols, more, chunk);
+        } else {
+            let _r = write!(out, "\x1b_Gm={};{}\x1b\\", more, chunk);
+        }
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+/// 使用 6x6x6 的调色板
+fn sixel(img: &RgbImage) -> String {
+    let (w, h) = img.dimensions();
+    let index = |x, y| {
+        let p = img.get_pixel(x, y).0;
+        let q = |c: u8| c as usize * 6 / 256;
+        q(p[0]) * 36 + q(p[1]) * 6 + q(p[2])
+    };
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", w, h);
+    let used = (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| index(x, y))
+        .collect::<BTreeSet<_>>();
+    for i in used.iter() {
+        let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+        let _r = write!(out, "#{};2;{};{};{}", i, r * 20, g * 20, b * 20);
+    }
+
+    for band in (0..h).step_by(6) {
+        let rows = (band..(band + 6).min(h)).collect::<Vec<_>>();
+        let colors = rows
+            .iter()
+            .flat_map(|&y| (0..w).map(move |x| index(x, y)))
+            .collect::<BTreeSet<_>>();
+        for color in colors {
+            let _r = write!(out, "#{}", color);
+            let line = (0..w)
+                .map(|x| {
+                    let bits = rows
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, &y)| index(x, y) == color)
+                        .fold(0u8, |acc, (k, _)| acc | (1 << k));
+                    (63 + bits) as char
+                })
+                .collect::<Vec<_>>();
+            // 游程编码
+            let mut x = 0;
+            while x < line.len() {
+                let c = line[x];
+                let run = line[x..].iter().take_while(|&&d| d == c).count();
+                if run > 3 {
+                    let _r = write!(out, "!{}{}", run, c);
+                } else {
+                    out.extend(std::iter::repeat(c).take(run));
+                }
+                x += run;
+            }
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+/// 每个字符显示上下两个像素
+fn blocks(img: &RgbImage, cols: u32) -> String {
+    let w = cols.min(img.width()).max(1);
+    let h = (img.height() * w / img.width()).max(2).div_ceil(2) * 2;
+    let img = image::imageops::resize(img, w, h, FilterType::Triangle);
+
+    let mut out = String::new();
+    for y in (0..h).step_by(2) {
+        for x in 0..w {
+            let [r1, g1, b1] = img.get_pixel(x, y).0;
+            let [r2, g2, b2] = img.get_pixel(x, y + 1).0;
+            let _r = write!(
+                out,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                r1, g1, b1, r2, g2, b2
+            );
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod termimg_test {
+    use super::*;
+
+    #[test]
+    fn blocks_test() {
+        let img = RgbImage::from_pixel(10, 20, image::Rgb([255, 0, 0]));
+        let out = blocks(&img, 5);
+        assert_eq!(out.lines().count(), 5);
+        assert!(out.starts_with("\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m▀"));
+
+        let out = sixel(&img);
+        assert!(out.starts_with("\x1bPq\"1;1;10;20#180;2;100;0;0#180"));
+        assert!(out.ends_with("-\x1b\\\n"));
+    }
+}
@@ -0,0 +1,128 @@
+//! 发送 http 请求的接口
+//!
+//! `TjuPtUser` 及 `PosterClient` 只通过 [`Transport`] 发送请求，默认使用 `reqwest::Client`，
+//! 也可以替换成带中间件、统计或者模拟响应的实现，测试时不需要监听端口
+
+use crate::error::Result;
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Client, Method, Request, Response, Url,
+};
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// 发送一个请求，返回响应
+///
+/// 需要自行处理重定向，响应的 `url()` 应该是重定向之后的链接
+#[async_trait]
+pub trait Transport: Debug + Send + Sync {
+    async fn execute(&self, req: Request) -> Result<Response>;
+}
+
+#[async_trait]
+impl Transport for Client {
+    async fn execute(&self, req: Request) -> Result<Response> {
+        Ok(Client::execute(self, req).await?)
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for std::sync::Arc<T> {
+    async fn execute(&self, req: Request) -> Result<Response> {
+        (**self).execute(req).await
+    }
+}
+
+/// GET 请求
+pub fn get(url: &str) -> Result<Request> {
+    Ok(Request::new(Method::GET, Url::parse(url)?))
+}
+
+/// 带参数的 GET 请求，参数加在链接原有的参数之后
+pub fn get_with_query<Q>(url: &str, query: &Q) -> Result<Request>
+where
+    Q: Serialize + ?Sized,
+{
+    let mut url = Url::parse(url)?;
+    let query = serde_urlencoded::to_string(query)?;
+    if !query.is_empty() {
+        let query = match url.query() {
+            Some(ori) if !ori.is_empty() => format!("{}&{}", ori, query),
+            _ => query,
+        };
+        url.set_query(Some(&query));
+    }
+    Ok(Request::new(Method::GET, url))
+}
+
+/// 提交表单的 POST 请求
+pub fn post_form<T>(url: &str, form: &T) -> Result<Request>
+where
+    T: Serialize + ?Sized,
+{
+    let mut req = Request::new(Method::POST, Url::parse(url)?);
+    req.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    );
+    *req.body_mut() = Some(serde_urlencoded::to_string(form)?.into());
+    Ok(req)
+}
+
+#[cfg(test)]
+mod transport_test {
+    use super::*;
+    use crate::{douban, picparser::PosterClient};
+    use reqwest::ResponseBuilderExt;
+    use std::sync::Mutex;
+
+    /// 返回固定的 json，并记下请求的链接
+    #[derive(Debug, Default)]
+    struct Fake {
+        urls: Mutex<Vec<String>>,
+    }
+
+    #[async_trait]
+    impl Transport for Fake {
+        async fn execute(&self, req: Request) -> Result<Response> {
+            self.urls.lock().unwrap().push(req.url().to_string());
+            let body = r#"[{"img": "http://fake/p.jpg", "title": "狮子王", "sub_title": "The Lion King", "year": "1994"}]"#;
+            let res = http::Response::builder()
+                .url(req.url().clone())
+                .body(body)?;
+            Ok(Response::from(res))
+        }
+    }
+
+    #[test]
+    fn request_test() {
+        let req = get_with_query("http://fake/s?a=1", &[("q", "狮子 王")]).unwrap();
+        assert_eq!(
+            req.url().as_str(),
+            "http://fake/s?a=1&q=%E7%8B%AE%E5%AD%90+%E7%8E%8B"
+        );
+
+        let req = post_form("http://fake/login", &[("a", "1"), ("b", "&")]).unwrap();
+        assert_eq!(req.method(), Method::POST);
+        assert_eq!(
+            req.body().and_then(|b| b.as_bytes()),
+            Some("a=1&b=%26".as_bytes())
+        );
+    }
+
+    #[tokio::test]
+    async fn fake_transport_test() {
+        let fake = std::sync::Arc::new(Fake::default());
+        let mut client = PosterClient::new(fake.clone());
+        client.set_suggest_url("http://fake/suggest");
+        let data = douban::search("狮子王 The Lion King", &client, 1)
+            .await
+            .unwrap();
+        assert_eq!(data[0].img, "http://fake/p.jpg");
+        assert_eq!(
+            fake.urls.lock().unwrap()[0],
+            "http://fake/suggest?q=%E7%8B%AE%E5%AD%90%E7%8E%8B"
+        );
+    }
+}
@@ -1,27 +1,89 @@
-use anyhow::Result;
-use simple_logger::SimpleLogger;
-
-#[tokio::main]
-async fn main() {
-    SimpleLogger::new()
-        .with_colors(true)
-        .with_level(log::LevelFilter::Debug)
-        .with_module_level("reqwest", log::LevelFilter::Error)
-        .with_module_level("cookie_store", log::LevelFilter::Error)
-        .with_module_level("selectors", log::LevelFilter::Error)
-        .with_module_level("html5ever", log::LevelFilter::Error)
-        .with_module_level("rustls", log::LevelFilter::Error)
-        .init()
-        .unwrap();
-
-    match mma().await {
-        Ok(_) => {}
-        Err(e) => log::error!("Error: {}", e),
+use clap::ArgMatches;
+use libs::{
+    error::Result,
+    logger::{LogDest, LogFilter, LogFormat, Logger},
+    report::RunStatus,
+};
+use log::LevelFilter;
+use std::{path::PathBuf, process::ExitCode};
+
+fn main() -> ExitCode {
+    // 本地时区只能在启动其他线程之前获取
+    libs::logger::init_local_offset();
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(RunStatus::Error.code());
+        }
+    };
+    runtime.block_on(run())
+}
+
+async fn run() -> ExitCode {
+    let mat = match libs::cliparser::cli_parser() {
+        Ok(mat) => mat,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return ExitCode::from(RunStatus::Error.code());
+        }
+    };
+
+    if let Err(e) = init_logger(&mat) {
+        eprintln!("Error: {}", e.report());
+        return ExitCode::from(RunStatus::Error.code());
     }
+
+    let status = match mma(mat).await {
+        Ok(status) => status,
+        Err(e) => {
+            log::error!("Error: {}", e.report());
+            RunStatus::Error
+        }
+    };
+    log::logger().flush();
+    ExitCode::from(status.code())
+}
+
+async fn mma(mat: ArgMatches) -> Result<RunStatus> {
+    libs::bot::attendance(mat).await
 }
 
-async fn mma() -> Result<()> {
-    // 解析命令行
-    libs::bot::attendance().await?;
-    Ok(())
+/// 根据命令行参数初始化日志
+fn init_logger(mat: &ArgMatches) -> Result<()> {
+    let level = match mat.get_count("verbose") as i8 - mat.get_count("quiet") as i8 {
+        i8::MIN..=-3 => LevelFilter::Off,
+        -2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+
+    let mut filter = LogFilter::new(level);
+    let spec = mat
+        .get_one::<String>("log-filter")
+        .cloned()
+        .or_else(|| std::env::var("RUST_LOG").ok());
+    if let Some(spec) = spec {
+        filter = filter.parse(&spec)?;
+    }
+
+    let format = match mat.get_one::<String>("log-format").map(String::as_str) {
+        Some("json") => LogFormat::Json,
+        _ => LogFormat::Text,
+    };
+
+    let dest = match mat.get_one::<String>("log-file") {
+        None => LogDest::Stderr,
+        Some(path) if path.is_empty() => LogDest::Rotating {
+            keep_days: *mat.get_one::<u64>("log-keep-days").unwrap(),
+        },
+        Some(path) => LogDest::File(PathBuf::from(path)),
+    };
+
+    Logger::new(filter, format, &dest)?.init()
 }
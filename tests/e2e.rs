@@ -0,0 +1,220 @@
+//! 使用模拟服务器的端到端测试
+
+use libs::{
+    bot::TjuPtUser,
+    cookie::MemoryStorage,
+    error::{ErrorKind, Result},
+    mock::{MockServer, Scenario, ANSWER, MOCK_PWD, MOCK_USER},
+    progress::{Event, Progress},
+    tape::Tape,
+};
+use std::sync::Arc;
+
+async fn user(server: &MockServer, pwd: &str) -> TjuPtUser {
+    TjuPtUser::builder(MOCK_USER, pwd)
+        .retry(1)
+        .site_url(&server.url())
+        .suggest_url(&server.suggest_url())
+        .build()
+        .unwrap()
+}
+
+async fn attend(scenario: Scenario) -> (MockServer, Result<String>) {
+    let server = MockServer::start(scenario).await.unwrap();
+    let user = user(&server, MOCK_PWD).await;
+    let res = user.att_now().await.map(|a| a.answer);
+    (server, res)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn success() {
+    let (server, res) = attend(Scenario::Success).await;
+    assert_eq!(res.unwrap(), "狮子王 The Lion King (1994)");
+    assert_eq!(server.posted(), vec![ANSWER.to_owned()]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dry_run() {
+    let server = MockServer::start(Scenario::Success).await.unwrap();
+    let user = user(&server, MOCK_PWD).await;
+    let answers = user.dry_run().await.unwrap();
+    assert_eq!(answers.len(), 4);
+    let best = answers
+        .iter()
+        .max_by(|a, b| a.score.unwrap_or(0.0).total_cmp(&b.score.unwrap_or(0.0)))
+        .unwrap();
+    assert_eq!(best.value, ANSWER);
+    // 重拍版的海报排在前面，但是原版的更相似
+    assert!(best.img_url.as_deref().unwrap().ends_with("/posters/2.png"));
+    // 豆瓣上找不到的选项
+    assert!(answers[3].score.is_none() && answers[3].error.is_some());
+    assert!(server.posted().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn attended() {
+    let (server, res) = attend(Scenario::Attended).await;
+    let e = res.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Parse);
+    assert!(e.report().to_string().contains("可能已经签到"));
+    assert!(server.posted().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn makeup() {
+    let (server, res) = attend(Scenario::Makeup).await;
+    assert!(res.unwrap_err().report().to_string().contains("需要补签"));
+    assert!(server.posted().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn layout_change() {
+    let (server, res) = attend(Scenario::LayoutChange).await;
+    assert!(res
+        .unwrap_err()
+        .report()
+        .to_string()
+        .contains("无法定位图片"));
+    assert!(server.posted().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wrong_password() {
+    let (server, res) = attend(Scenario::WrongPassword).await;
+    let e = res.unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::Auth);
+    assert!(e.report().to_string().contains("登录失败"));
+    assert!(server.posted().is_empty());
+
+    // 密码不对时同样无法登录
+    let server = MockServer::start(Scenario::Success).await.unwrap();
+    let user = user(&server, "wrong").await;
+    assert!(user.dry_run().await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shared_cookie() {
+    let server = MockServer::start(Scenario::Success).await.unwrap();
+    let storage = Arc::new(MemoryStorage::default());
+    let builder = || {
+        TjuPtUser::builder(MOCK_USER, MOCK_PWD)
+            .retry(1)
+            .site_url(&server.url())
+            .suggest_url(&server.suggest_url())
+            .cookie_storage(storage.clone())
+            .build()
+            .unwrap()
+    };
+
+    let first = builder();
+    assert!(first.check_cookie().await.is_err());
+    first.att_now().await.unwrap();
+    // 销毁时保存 cookie
+    drop(first);
+
+    let second = builder();
+    assert!(second.check_cookie().await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn progress_events() {
+    let server = MockServer::start(Scenario::Success).await.unwrap();
+    let (progress, mut rx) = Progress::channel();
+    let mut user = user(&server, MOCK_PWD).await;
+    user.set_progress(progress);
+    user.att_now().await.unwrap();
+    drop(user);
+
+    let mut events = vec![];
+    while let Some(e) = rx.recv().await {
+        assert_eq!(e.user, MOCK_USER);
+        events.push(e.event);
+    }
+    assert_eq!(
+        events[..4],
+        [
+            Event::AttemptStarted {
+                attempt: 1,
+                retry: 1
+            },
+            Event::LoginStarted,
+            Event::LoginSucceeded,
+            Event::PageParsed { options: 4 },
+        ]
+    );
+    // 找到相似度足够的选项后就不再比较
+    assert!(events[4..events.len() - 2]
+        .iter()
+        .all(|e| matches!(e, Event::OptionScored { .. })));
+    assert!(matches!(
+        &events[events.len() - 2],
+        Event::AnswerSubmitted {
+            score: Some(_),
+            accepted: true,
+            ..
+        }
+    ));
+    assert_eq!(
+        events.last(),
+        Some(&Event::Finished {
+            answer: Some("狮子王 The Lion King (1994)".into()),
+            attempts: 1,
+            error: None,
+        })
+    );
+
+    // 失败时每次重试之前发出 `RetryScheduled`
+    let server = MockServer::start(Scenario::Attended).await.unwrap();
+    let (progress, mut rx) = Progress::channel();
+    let user = TjuPtUser::builder(MOCK_USER, MOCK_PWD)
+        .retry(2)
+        .site_url(&server.url())
+        .suggest_url(&server.suggest_url())
+        .progress(progress)
+        .build()
+        .unwrap();
+    assert!(user.att_now().await.is_err());
+    drop(user);
+    let mut events = vec![];
+    while let Some(e) = rx.recv().await {
+        events.push(e.event);
+    }
+    assert_eq!(
+        events
+            .iter()
+            .filter(|e| matches!(e, Event::RetryScheduled { attempt: 1, .. }))
+            .count(),
+        1
+    );
+    assert!(matches!(
+        events.last(),
+        Some(Event::Finished {
+            answer: None,
+            attempts: 2,
+            error: Some(_)
+        })
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn record_replay() {
+    let dir = std::env::temp_dir().join(format!("tjuptatt_e2e_tape_{}", std::process::id()));
+    let server = MockServer::start(Scenario::Success).await.unwrap();
+    let mut recorded = user(&server, MOCK_PWD).await;
+    recorded.set_tape(Tape::record(&dir, &[MOCK_PWD]).unwrap());
+    let answer = recorded.dry_run().await.unwrap();
+
+    // 服务器关闭后只能使用记录的响应，链接需要与记录时相同
+    let mut replayed = user(&server, MOCK_PWD).await;
+    drop(server);
+    replayed.set_tape(Tape::replay(&dir, &[MOCK_PWD]).unwrap());
+    let replayed = replayed.dry_run().await.unwrap();
+    assert_eq!(
+        replayed.iter().map(|a| a.score).collect::<Vec<_>>(),
+        answer.iter().map(|a| a.score).collect::<Vec<_>>()
+    );
+
+    let tape = std::fs::read_to_string(dir.join("tape.json")).unwrap();
+    assert!(!tape.contains(MOCK_PWD));
+    std::fs::remove_dir_all(&dir).unwrap();
+}